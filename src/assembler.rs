@@ -0,0 +1,763 @@
+use crate::opcode::Opcode;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+const ROM_START: u32 = 0x200;
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_CONSTANT_DEPTH: usize = 64;
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SCD", "SCU", "SCRU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "MEGAOFF", "MEGAON",
+    "LDHI", "LDPAL", "SPRW", "SPRH", "ALPHA", "DIGISND", "STOPSND", "BMODE", "CCOL", "SYS", "JP",
+    "CALL", "SE", "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN",
+    "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+// Binary operators from lowest to highest precedence.
+const OPERATORS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Symbol(&'static str),
+}
+
+struct Spanned {
+    token: Token,
+    column: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Keyword {
+    I,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    V(u8),
+    Indirect,
+    Key(Keyword),
+    Long(usize),
+    Expr(usize),
+}
+
+enum Statement {
+    Empty,
+    Instruction { mnemonic: String, operands: Vec<Operand>, ranges: Vec<Range<usize>> },
+    Bytes(Vec<Range<usize>>),
+    Words(Vec<Range<usize>>),
+    Org(Range<usize>),
+    Constant(String, Range<usize>),
+    Include(String),
+}
+
+struct Line {
+    file: String,
+    number: usize,
+    tokens: Vec<Spanned>,
+    end_column: usize,
+    label: Option<(String, usize)>,
+    statement: Statement,
+}
+
+impl Line {
+    fn error(&self, column: usize, message: impl Into<String>) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.number, column, message: message.into() }
+    }
+
+    fn column(&self, range: &Range<usize>) -> usize {
+        self.tokens.get(range.start).map_or(self.end_column, |spanned| spanned.column)
+    }
+}
+
+struct Constant {
+    line: usize,
+    range: Range<usize>,
+    pc: u32,
+}
+
+#[derive(Default)]
+struct Assembler {
+    lines: Vec<Line>,
+    labels: HashMap<String, u32>,
+    constants: HashMap<String, Constant>,
+}
+
+// Assembles source text into a ROM image loaded at 0x200. Includes are resolved
+// relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::default();
+    assembler.load("<source>", source, Path::new("."), 0)?;
+    assembler.run()
+}
+
+// Assembles a source file; includes are resolved relative to the including file.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: e.to_string(),
+    })?;
+    let mut assembler = Assembler::default();
+    let dir = path.parent().unwrap_or(Path::new("."));
+    assembler.load(&path.display().to_string(), &source, dir, 0)?;
+    assembler.run()
+}
+
+fn tokenize(file: &str, number: usize, text: &str) -> Result<(Vec<Spanned>, usize), AsmError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut end = chars.len();
+    let error = |column: usize, message: String| AsmError { file: file.to_string(), line: number, column, message };
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let column = pos + 1;
+        if c == ';' {
+            end = pos;
+            break;
+        }
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        let token = if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_' || chars[pos] == '.') {
+                pos += 1;
+            }
+            Token::Ident(chars[start..pos].iter().collect())
+        } else if c.is_ascii_digit() {
+            let (radix, start) = match chars.get(pos + 1) {
+                Some('x' | 'X') if c == '0' => (16, pos + 2),
+                Some('b' | 'B') if c == '0' => (2, pos + 2),
+                _ => (10, pos),
+            };
+            pos = start;
+            while pos < chars.len() && chars[pos].is_ascii_alphanumeric() {
+                pos += 1;
+            }
+            let digits: String = chars[start..pos].iter().collect();
+            let value = i64::from_str_radix(&digits, radix)
+                .map_err(|_| error(column, format!("invalid number `{}`", chars[column - 1..pos].iter().collect::<String>())))?;
+            Token::Number(value)
+        } else if c == '"' {
+            let mut bytes = Vec::new();
+            pos += 1;
+            loop {
+                let c = *chars.get(pos).ok_or_else(|| error(column, "unterminated string".to_string()))?;
+                pos += 1;
+                match c {
+                    '"' => break,
+                    '\\' => {
+                        let escaped = *chars.get(pos).ok_or_else(|| error(column, "unterminated string".to_string()))?;
+                        pos += 1;
+                        bytes.push(match escaped {
+                            'n' => b'\n',
+                            '0' => 0,
+                            '\\' | '"' => escaped as u8,
+                            _ => return Err(error(pos - 1, format!("unknown escape `\\{}`", escaped))),
+                        });
+                    }
+                    _ if c.is_ascii() => bytes.push(c as u8),
+                    _ => return Err(error(pos, format!("non-ASCII character `{}` in string", c))),
+                }
+            }
+            Token::Str(bytes)
+        } else {
+            let two: String = chars[pos..chars.len().min(pos + 2)].iter().collect();
+            let symbol = match two.as_str() {
+                "<<" => "<<",
+                ">>" => ">>",
+                _ => match c {
+                    ',' => ",",
+                    ':' => ":",
+                    '[' => "[",
+                    ']' => "]",
+                    '(' => "(",
+                    ')' => ")",
+                    '+' => "+",
+                    '-' => "-",
+                    '*' => "*",
+                    '/' => "/",
+                    '%' => "%",
+                    '&' => "&",
+                    '|' => "|",
+                    '^' => "^",
+                    '~' => "~",
+                    '=' => "=",
+                    '$' => "$",
+                    _ => return Err(error(column, format!("unexpected character `{}`", c))),
+                },
+            };
+            pos += symbol.len();
+            Token::Symbol(symbol)
+        };
+        tokens.push(Spanned { token, column });
+    }
+
+    Ok((tokens, end + 1))
+}
+
+fn is_symbol(tokens: &[Spanned], index: usize, symbol: &str) -> bool {
+    matches!(tokens.get(index), Some(Spanned { token: Token::Symbol(s), .. }) if *s == symbol)
+}
+
+fn ident(tokens: &[Spanned], index: usize) -> Option<&str> {
+    match tokens.get(index) {
+        Some(Spanned { token: Token::Ident(name), .. }) => Some(name),
+        _ => None,
+    }
+}
+
+fn register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+fn keyword(name: &str) -> Option<Keyword> {
+    match name.to_ascii_uppercase().as_str() {
+        "I" => Some(Keyword::I),
+        "DT" => Some(Keyword::DT),
+        "ST" => Some(Keyword::ST),
+        "K" => Some(Keyword::K),
+        "F" => Some(Keyword::F),
+        "HF" => Some(Keyword::HF),
+        "B" => Some(Keyword::B),
+        "R" => Some(Keyword::R),
+        _ => None,
+    }
+}
+
+// Splits `start..end` at commas that are not nested in brackets or parentheses.
+fn split_operands(line: &Line, start: usize) -> Result<Vec<Range<usize>>, AsmError> {
+    let mut ranges = Vec::new();
+    if start >= line.tokens.len() {
+        return Ok(ranges);
+    }
+
+    let mut depth = 0;
+    let mut operand_start = start;
+    for index in start..line.tokens.len() {
+        match line.tokens[index].token {
+            Token::Symbol("(" | "[") => depth += 1,
+            Token::Symbol(")" | "]") => depth -= 1,
+            Token::Symbol(",") if depth == 0 => {
+                if operand_start == index {
+                    return Err(line.error(line.tokens[index].column, "missing operand"));
+                }
+                ranges.push(operand_start..index);
+                operand_start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    if operand_start == line.tokens.len() {
+        return Err(line.error(line.end_column, "missing operand"));
+    }
+    ranges.push(operand_start..line.tokens.len());
+    Ok(ranges)
+}
+
+fn classify(tokens: &[Spanned], range: &Range<usize>, index: usize) -> Operand {
+    let operand = &tokens[range.clone()];
+    if operand.len() == 1 {
+        if let Some(name) = ident(operand, 0) {
+            if let Some(x) = register(name) {
+                return Operand::V(x);
+            }
+            if let Some(keyword) = keyword(name) {
+                return Operand::Key(keyword);
+            }
+        }
+    }
+    if operand.len() == 3 && is_symbol(operand, 0, "[") && is_symbol(operand, 2, "]") {
+        if let Some(Keyword::I) = ident(operand, 1).and_then(keyword) {
+            return Operand::Indirect;
+        }
+    }
+    if operand.len() > 1 && ident(operand, 0).is_some_and(|name| name.eq_ignore_ascii_case("LONG")) {
+        return Operand::Long(index);
+    }
+    Operand::Expr(index)
+}
+
+fn parse_statement(line: &Line) -> Result<(Option<(String, usize)>, Statement), AsmError> {
+    let tokens = &line.tokens;
+    let mut start = 0;
+    let mut label = None;
+
+    if let (Some(name), true) = (ident(tokens, 0), is_symbol(tokens, 1, ":")) {
+        label = Some((name.to_string(), tokens[0].column));
+        start = 2;
+    }
+    let Some(first) = tokens.get(start) else {
+        return Ok((label, Statement::Empty));
+    };
+    let Token::Ident(name) = &first.token else {
+        return Err(line.error(first.column, "expected an instruction or directive"));
+    };
+
+    let is_constant = is_symbol(tokens, start + 1, "=")
+        || ident(tokens, start + 1).is_some_and(|word| word.eq_ignore_ascii_case("EQU"));
+    if is_constant {
+        if label.is_some() {
+            return Err(line.error(first.column, "a constant cannot follow a label"));
+        }
+        if start + 2 >= tokens.len() {
+            return Err(line.error(line.end_column, "missing constant value"));
+        }
+        return Ok((None, Statement::Constant(name.clone(), start + 2..tokens.len())));
+    }
+
+    let mnemonic = name.trim_start_matches('.').to_ascii_uppercase();
+    let ranges = split_operands(line, start + 1)?;
+    let statement = match mnemonic.as_str() {
+        "DB" => Statement::Bytes(ranges),
+        "DW" => Statement::Words(ranges),
+        "ORG" | "INCLUDE" if ranges.len() != 1 => {
+            return Err(line.error(first.column, format!("`{}` takes exactly one operand", name)));
+        }
+        "ORG" => Statement::Org(ranges[0].clone()),
+        "INCLUDE" => match &tokens[ranges[0].clone()] {
+            [Spanned { token: Token::Str(path), .. }] => Statement::Include(String::from_utf8_lossy(path).into_owned()),
+            _ => return Err(line.error(line.column(&ranges[0]), "expected a quoted file name")),
+        },
+        _ => {
+            let operands = ranges.iter().enumerate().map(|(index, range)| classify(tokens, range, index)).collect();
+            Statement::Instruction { mnemonic, operands, ranges }
+        }
+    };
+    Ok((label, statement))
+}
+
+struct Evaluator<'a> {
+    assembler: &'a Assembler,
+    line: &'a Line,
+    tokens: &'a [Spanned],
+    pos: usize,
+    pc: u32,
+    lenient: bool,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.line.end_column, |spanned| spanned.column)
+    }
+
+    fn symbol(&self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Spanned { token: Token::Symbol(s), .. }) => symbols.iter().copied().find(|symbol| symbol == s),
+            _ => None,
+        }
+    }
+
+    fn evaluate(&mut self) -> Result<i64, AsmError> {
+        let value = self.binary(0)?;
+        if self.pos < self.tokens.len() {
+            return Err(self.line.error(self.column(), "unexpected token in expression"));
+        }
+        Ok(value)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, AsmError> {
+        if level == OPERATORS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(operator) = self.symbol(OPERATORS[level]) {
+            let column = self.column();
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = match operator {
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "<<" => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shl(rhs)).unwrap_or(0),
+                ">>" => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shr(rhs)).unwrap_or(0),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                _ if rhs == 0 && self.lenient => 0,
+                _ if rhs == 0 => return Err(self.line.error(column, "division by zero")),
+                "/" => lhs.wrapping_div(rhs),
+                _ => lhs.wrapping_rem(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, AsmError> {
+        match self.symbol(&["-", "~", "+"]) {
+            Some(operator) => {
+                self.pos += 1;
+                let value = self.unary()?;
+                Ok(match operator {
+                    "-" => value.wrapping_neg(),
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, AsmError> {
+        let column = self.column();
+        let Some(spanned) = self.tokens.get(self.pos) else {
+            return Err(self.line.error(column, "expected an expression"));
+        };
+        self.pos += 1;
+
+        match &spanned.token {
+            Token::Number(value) => Ok(*value),
+            Token::Symbol("$") => Ok(self.pc as i64),
+            Token::Symbol("(") => {
+                let value = self.binary(0)?;
+                if self.symbol(&[")"]).is_none() {
+                    return Err(self.line.error(self.column(), "expected `)`"));
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            Token::Ident(name) => self.lookup(name, column),
+            _ => Err(self.line.error(column, "expected an expression")),
+        }
+    }
+
+    fn lookup(&self, name: &str, column: usize) -> Result<i64, AsmError> {
+        if let Some(&address) = self.assembler.labels.get(name) {
+            return Ok(address as i64);
+        }
+        if let Some(constant) = self.assembler.constants.get(name) {
+            if self.depth >= MAX_CONSTANT_DEPTH {
+                return Err(self.line.error(column, format!("constant `{}` refers to itself", name)));
+            }
+            return self.assembler.evaluate(constant.line, &constant.range, constant.pc, self.lenient, self.depth + 1);
+        }
+        if self.lenient {
+            return Ok(0);
+        }
+        Err(self.line.error(column, format!("undefined symbol `{}`", name)))
+    }
+}
+
+impl Assembler {
+    fn load(&mut self, file: &str, source: &str, dir: &Path, depth: usize) -> Result<(), AsmError> {
+        for (index, text) in source.lines().enumerate() {
+            let (tokens, end_column) = tokenize(file, index + 1, text)?;
+            let mut line = Line {
+                file: file.to_string(),
+                number: index + 1,
+                tokens,
+                end_column,
+                label: None,
+                statement: Statement::Empty,
+            };
+            (line.label, line.statement) = parse_statement(&line)?;
+
+            if let Statement::Include(include) = &line.statement {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(line.error(line.tokens[0].column, "includes are nested too deeply"));
+                }
+                let path = dir.join(include);
+                let source = fs::read_to_string(&path)
+                    .map_err(|e| line.error(line.tokens[0].column, format!("cannot read `{}`: {}", path.display(), e)))?;
+                let include_dir = path.parent().unwrap_or(dir).to_path_buf();
+                line.statement = Statement::Empty;
+                self.lines.push(line);
+                self.load(&path.display().to_string(), &source, &include_dir, depth + 1)?;
+            } else {
+                self.lines.push(line);
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(&self, line: usize, range: &Range<usize>, pc: u32, lenient: bool, depth: usize) -> Result<i64, AsmError> {
+        let line = &self.lines[line];
+        Evaluator { assembler: self, line, tokens: &line.tokens[range.clone()], pos: 0, pc, lenient, depth }.evaluate()
+    }
+
+    fn value(&self, line: usize, range: &Range<usize>, pc: u32, lenient: bool, bounds: Range<i64>) -> Result<i64, AsmError> {
+        let value = self.evaluate(line, range, pc, lenient, 0)?;
+        if !bounds.contains(&value) {
+            let line = &self.lines[line];
+            return Err(line.error(
+                line.column(range),
+                format!("value {} is out of range ({}..={})", value, bounds.start, bounds.end - 1),
+            ));
+        }
+        Ok(value)
+    }
+
+    // Builds the opcode for an instruction line. In the first pass undefined
+    // symbols evaluate to zero, which is enough to know the instruction size.
+    fn instruction(&self, index: usize, pc: u32, lenient: bool) -> Result<Opcode, AsmError> {
+        use Keyword::*;
+        use Operand::*;
+
+        let line = &self.lines[index];
+        let Statement::Instruction { mnemonic, operands, ranges } = &line.statement else {
+            unreachable!("not an instruction line");
+        };
+        let value = |operand: usize, bounds: Range<i64>| {
+            let mut range = ranges[operand].clone();
+            if let Long(_) = operands[operand] {
+                range.start += 1;
+            }
+            self.value(index, &range, pc, lenient, bounds)
+        };
+        let addr = |operand| value(operand, 0..0x1000).map(|nnn| nnn as u16);
+        let byte = |operand| value(operand, -0x80..0x100).map(|kk| kk as u8);
+        let nibble = |operand| value(operand, 0..0x10).map(|n| n as u8);
+
+        let opcode = match (mnemonic.as_str(), operands.as_slice()) {
+            ("CLS", []) => Opcode::CLS,
+            ("RET", []) => Opcode::RET,
+            ("SCD", [Expr(n)]) => Opcode::SCD_nibble(nibble(*n)?),
+            ("SCU", [Expr(n)]) => Opcode::SCU_nibble(nibble(*n)?),
+            ("SCRU", [Expr(n)]) => Opcode::SCRU_nibble(nibble(*n)?),
+            ("SCR", []) => Opcode::SCR,
+            ("SCL", []) => Opcode::SCL,
+            ("EXIT", []) => Opcode::EXIT,
+            ("LOW", []) => Opcode::LOW,
+            ("HIGH", []) => Opcode::HIGH,
+            ("MEGAOFF", []) => Opcode::MEGAOFF,
+            ("MEGAON", []) => Opcode::MEGAON,
+            ("LDHI", [Key(I), Expr(nnnnnn)]) => Opcode::LDHI_I(value(*nnnnnn, 0..0x1000000)? as u32),
+            ("LDPAL", [Expr(kk)]) => Opcode::LDPAL(byte(*kk)?),
+            ("SPRW", [Expr(kk)]) => Opcode::SPRW(byte(*kk)?),
+            ("SPRH", [Expr(kk)]) => Opcode::SPRH(byte(*kk)?),
+            ("ALPHA", [Expr(kk)]) => Opcode::ALPHA(byte(*kk)?),
+            ("DIGISND", [Expr(n)]) => Opcode::DIGISND(nibble(*n)?),
+            ("STOPSND", []) => Opcode::STOPSND,
+            ("BMODE", [Expr(n)]) => Opcode::BMODE(nibble(*n)?),
+            ("CCOL", [Expr(kk)]) => Opcode::CCOL(byte(*kk)?),
+            ("SYS", [Expr(nnn)]) => Opcode::SYS(addr(*nnn)?),
+            ("JP", [Expr(nnn)]) => Opcode::JP(addr(*nnn)?),
+            ("JP", [V(0), Expr(nnn)]) => Opcode::JP_V0_addr(addr(*nnn)?),
+            ("CALL", [Expr(nnn)]) => Opcode::CALL(addr(*nnn)?),
+            ("SE", [V(x), V(y)]) => Opcode::SE_Vx_Vy(*x, *y),
+            ("SE", [V(x), Expr(kk)]) => Opcode::SE_Vx_byte(*x, byte(*kk)?),
+            ("SNE", [V(x), V(y)]) => Opcode::SNE_Vx_Vy(*x, *y),
+            ("SNE", [V(x), Expr(kk)]) => Opcode::SNE_Vx_byte(*x, byte(*kk)?),
+            ("SAVE", [V(x), V(y)]) => Opcode::SAVE_Vx_Vy(*x, *y),
+            ("LOAD", [V(x), V(y)]) => Opcode::LOAD_Vx_Vy(*x, *y),
+            ("LD", [V(x), V(y)]) => Opcode::LD_Vx_Vy(*x, *y),
+            ("LD", [V(x), Key(DT)]) => Opcode::LD_Vx_DT(*x),
+            ("LD", [V(x), Key(K)]) => Opcode::LD_Vx_K(*x),
+            ("LD", [V(x), Key(R)]) => Opcode::LD_Vx_R(*x),
+            ("LD", [V(x), Indirect]) => Opcode::LD_Vx_I(*x),
+            ("LD", [V(x), Expr(kk)]) => Opcode::LD_Vx_byte(*x, byte(*kk)?),
+            ("LD", [Key(I), Long(nnnn)]) => Opcode::LD_I_long(value(*nnnn, 0..0x10000)? as u16),
+            ("LD", [Key(I), Expr(nnn)]) => Opcode::LD_I_addr(addr(*nnn)?),
+            ("LD", [Key(DT), V(x)]) => Opcode::LD_DT_Vx(*x),
+            ("LD", [Key(ST), V(x)]) => Opcode::LD_ST_Vx(*x),
+            ("LD", [Key(F), V(x)]) => Opcode::LD_F_Vx(*x),
+            ("LD", [Key(HF), V(x)]) => Opcode::LD_HF_Vx(*x),
+            ("LD", [Key(B), V(x)]) => Opcode::LD_B_Vx(*x),
+            ("LD", [Key(R), V(x)]) => Opcode::LD_R_Vx(*x),
+            ("LD", [Indirect, V(x)]) => Opcode::LD_I_Vx(*x),
+            ("ADD", [V(x), V(y)]) => Opcode::ADD_Vx_Vy(*x, *y),
+            ("ADD", [V(x), Expr(kk)]) => Opcode::ADD_Vx_byte(*x, byte(*kk)?),
+            ("ADD", [Key(I), V(x)]) => Opcode::ADD_I_Vx(*x),
+            ("OR", [V(x), V(y)]) => Opcode::OR_Vx_Vy(*x, *y),
+            ("AND", [V(x), V(y)]) => Opcode::AND_Vx_Vy(*x, *y),
+            ("XOR", [V(x), V(y)]) => Opcode::XOR_Vx_Vy(*x, *y),
+            ("SUB", [V(x), V(y)]) => Opcode::SUB_Vx_Vy(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Opcode::SUBN_Vx_Vy(*x, *y),
+            // A single-register shift uses Vx as the source so it behaves the same with or without the shift quirk.
+            ("SHR", [V(x)]) => Opcode::SHR_Vx_Vy(*x, *x),
+            ("SHR", [V(x), V(y)]) => Opcode::SHR_Vx_Vy(*x, *y),
+            ("SHL", [V(x)]) => Opcode::SHL_Vx_Vy(*x, *x),
+            ("SHL", [V(x), V(y)]) => Opcode::SHL_Vx_Vy(*x, *y),
+            ("RND", [V(x), Expr(kk)]) => Opcode::RND_Vx_byte(*x, byte(*kk)?),
+            ("DRW", [V(x), V(y), Expr(n)]) => Opcode::DRW_Vx_Vy_nibble(*x, *y, nibble(*n)?),
+            ("SKP", [V(x)]) => Opcode::SKP_Vx(*x),
+            ("SKNP", [V(x)]) => Opcode::SKNP_Vx(*x),
+            ("PLANE", [Expr(n)]) => Opcode::PLANE(nibble(*n)?),
+            ("AUDIO", []) => Opcode::AUDIO,
+            ("PITCH", [V(x)]) => Opcode::PITCH_Vx(*x),
+            _ => {
+                let column = line.label.as_ref().map_or(0, |_| 2);
+                let column = line.tokens[column].column;
+                return Err(if MNEMONICS.contains(&mnemonic.as_str()) {
+                    line.error(column, format!("invalid operands for `{}`", mnemonic))
+                } else {
+                    line.error(column, format!("unknown instruction `{}`", mnemonic))
+                });
+            }
+        };
+        Ok(opcode)
+    }
+
+    fn data(&self, index: usize, pc: u32, lenient: bool, bytes: &mut Vec<u8>) -> Result<(), AsmError> {
+        let line = &self.lines[index];
+        match &line.statement {
+            Statement::Bytes(ranges) => {
+                for range in ranges {
+                    match &line.tokens[range.clone()] {
+                        [Spanned { token: Token::Str(string), .. }] => bytes.extend_from_slice(string),
+                        _ => bytes.push(self.value(index, range, pc, lenient, -0x80..0x100)? as u8),
+                    }
+                }
+            }
+            Statement::Words(ranges) => {
+                for range in ranges {
+                    let word = self.value(index, range, pc, lenient, -0x8000..0x10000)? as u16;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        // First pass: assign addresses to labels and constants.
+        let mut pc = ROM_START;
+        let mut scratch = Vec::new();
+        for index in 0..self.lines.len() {
+            if let Some((name, column)) = self.lines[index].label.clone() {
+                self.define(index, &name, column)?;
+                self.labels.insert(name, pc);
+            }
+
+            match &self.lines[index].statement {
+                Statement::Instruction { .. } => pc += self.instruction(index, pc, true)?.size() as u32,
+                Statement::Bytes(_) | Statement::Words(_) => {
+                    scratch.clear();
+                    self.data(index, pc, true, &mut scratch)?;
+                    pc += scratch.len() as u32;
+                }
+                Statement::Org(range) => pc = self.org(index, range, pc)?,
+                Statement::Constant(name, range) => {
+                    let (name, range) = (name.clone(), range.clone());
+                    self.define(index, &name, self.lines[index].tokens[0].column)?;
+                    self.constants.insert(name, Constant { line: index, range, pc });
+                }
+                Statement::Empty | Statement::Include(_) => (),
+            }
+        }
+
+        // Second pass: every symbol is known, so emit the bytes.
+        let mut rom = Vec::new();
+        for index in 0..self.lines.len() {
+            let pc = ROM_START + rom.len() as u32;
+            match &self.lines[index].statement {
+                Statement::Instruction { .. } => self.instruction(index, pc, false)?.encode_into(&mut rom),
+                Statement::Bytes(_) | Statement::Words(_) => self.data(index, pc, false, &mut rom)?,
+                Statement::Org(range) => {
+                    let address = self.org(index, range, pc)?;
+                    rom.resize((address - ROM_START) as usize, 0);
+                }
+                _ => (),
+            }
+        }
+        Ok(rom)
+    }
+
+    fn define(&self, index: usize, name: &str, column: usize) -> Result<(), AsmError> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(self.lines[index].error(column, format!("`{}` is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn org(&self, index: usize, range: &Range<usize>, pc: u32) -> Result<u32, AsmError> {
+        let address = self.value(index, range, pc, false, 0..0x1000000)? as u32;
+        if address < pc {
+            let line = &self.lines[index];
+            return Err(line.error(line.column(range), format!("org 0x{:X} is behind the current address 0x{:X}", address, pc)));
+        }
+        Ok(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::disassemble;
+    use crate::platform::Platform;
+
+    #[test]
+    fn disassembly_reassembles_to_the_same_rom() {
+        let rom = include_bytes!("games/test_opcode.ch8");
+        assert_eq!(assemble(&disassemble(rom, Platform::Chip8, true)).unwrap(), rom);
+    }
+
+    #[test]
+    fn extended_instructions_round_trip() {
+        let roms: [(Platform, &[u8]); 2] = [
+            (Platform::XoChip, &[0xF0, 0x00, 0x02, 0x0A, 0x51, 0x22, 0xF2, 0x01, 0x00, 0xD3, 0x12, 0x00]),
+            (Platform::MegaChip, &[0x00, 0x11, 0x01, 0x01, 0x00, 0x00, 0x03, 0x10, 0x08, 0x02, 0x12, 0x00]),
+        ];
+        for (platform, rom) in roms {
+            assert_eq!(assemble(&disassemble(rom, platform, true)).unwrap(), rom, "{}", platform);
+        }
+    }
+
+    #[test]
+    fn labels_constants_and_expressions() {
+        let source = "
+            SPRITE_ROWS equ rows_end - rows
+            start:
+                LD I, rows
+                DRW V0, V1, SPRITE_ROWS
+                JP $        ; spin
+            rows:
+                db 0b11110000, 0x90, (1 << 4) | 0x80
+            rows_end:
+                dw start + 2
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0xA2, 0x06, 0xD0, 0x13, 0x12, 0x04, 0xF0, 0x90, 0x90, 0x02, 0x02]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_source() {
+        let error = assemble("CLS\n  JP nowhere\n").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 6));
+        assert!(assemble("LD V0, 0x100").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioPattern {
+    pub buffer: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    // XO-CHIP plays the 128 one-bit samples at 4000 * 2^((pitch - 64) / 48) Hz.
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    pub fn bit(&self, index: usize) -> bool {
+        let index = index % (AUDIO_PATTERN_SIZE * 8);
+        self.buffer[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SquareWave {
+    frequency: f32,
+    volume: f32,
+    sample_rate: u32,
+    phase: f32,
+}
+
+impl SquareWave {
+    pub fn new(frequency: f32, volume: f32, sample_rate: u32) -> SquareWave {
+        SquareWave {
+            frequency,
+            volume: volume.clamp(0.0, 1.0),
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+        self.phase = (self.phase + self.frequency / self.sample_rate as f32) % 1.0;
+        sample
+    }
+
+    pub fn fill(&mut self, buffer: &mut [f32], playing: bool) {
+        for sample in buffer.iter_mut() {
+            *sample = if playing { self.next_sample() } else { 0.0 };
+        }
+    }
+}
+
+// MegaChip digitised sound: unsigned 8-bit mono samples.
+#[derive(Clone, Debug)]
+pub struct DigitalSound {
+    pub sample_rate: u32,
+    pub samples: Arc<[u8]>,
+    pub looping: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AudioState {
+    pub beeping: bool,
+    pub pattern: Option<AudioPattern>,
+    pub digital: Option<DigitalSound>,
+}
+
+// Plays the plain square wave, the XO-CHIP sample pattern once a ROM has loaded one,
+// or MegaChip digitised sound, which overrides both while it is playing.
+#[derive(Clone, Debug)]
+pub struct Beeper {
+    square: SquareWave,
+    state: AudioState,
+    volume: f32,
+    sample_rate: u32,
+    position: f32,
+    digital_position: f64,
+}
+
+impl Beeper {
+    pub fn new(frequency: f32, volume: f32, sample_rate: u32) -> Beeper {
+        Beeper {
+            square: SquareWave::new(frequency, volume, sample_rate),
+            state: AudioState::default(),
+            volume: volume.clamp(0.0, 1.0),
+            sample_rate,
+            position: 0.0,
+            digital_position: 0.0,
+        }
+    }
+
+    pub fn set_state(&mut self, state: AudioState) {
+        let same_sound = match (&self.state.digital, &state.digital) {
+            (Some(current), Some(next)) => Arc::ptr_eq(&current.samples, &next.samples),
+            _ => false,
+        };
+        if !same_sound {
+            self.digital_position = 0.0;
+        }
+        self.state = state;
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        if let Some(digital) = &self.state.digital {
+            let mut index = self.digital_position as usize;
+            if digital.looping && !digital.samples.is_empty() {
+                index %= digital.samples.len();
+            }
+            self.digital_position += digital.sample_rate as f64 / self.sample_rate as f64;
+            return match digital.samples.get(index) {
+                Some(&sample) => (sample as f32 - 128.0) / 128.0 * self.volume,
+                None => 0.0,
+            };
+        }
+        if !self.state.beeping {
+            return 0.0;
+        }
+
+        let Some(pattern) = self.state.pattern else {
+            return self.square.next_sample();
+        };
+        let sample = if pattern.bit(self.position as usize) { self.volume } else { -self.volume };
+        self.position = (self.position + pattern.playback_rate() / self.sample_rate as f32)
+            % (AUDIO_PATTERN_SIZE * 8) as f32;
+        sample
+    }
+
+    pub fn fill(&mut self, buffer: &mut [f32]) {
+        for sample in buffer.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+}
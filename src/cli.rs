@@ -1,4 +1,11 @@
-use clap::{Parser, Subcommand};
+use crate::keymap::{Keymap, DEFAULT_KEYMAP};
+use crate::palette::{Palette, DEFAULT_PALETTE};
+use crate::video::VideoFormat;
+use chip8_emulator::audio::{DEFAULT_FREQUENCY, DEFAULT_VOLUME};
+use chip8_emulator::constants::{DEFAULT_CYCLES_PER_FRAME, SCALE_FACTOR};
+use chip8_emulator::rewind::{DEFAULT_REWIND_MEMORY, DEFAULT_REWIND_SECONDS};
+use chip8_emulator::{Platform, Quirks};
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "Chip8-Emulator")]
@@ -9,8 +16,107 @@ pub struct Cli {
 
 #[derive(Subcommand)]
 pub enum Commands {
-    Start,
+    Start(StartCommand),
     Add(AddCommand),
+    Run(RunCommand),
+    Disasm(DisasmCommand),
+    Asm(AsmCommand),
+    Compile(CompileCommand),
+}
+
+#[derive(Args)]
+pub struct EmulatorOptions {
+    #[arg(long, default_value_t = DEFAULT_CYCLES_PER_FRAME)]
+    pub cycles_per_frame: u32,
+    /// Keyboard keys for the hex keypad, given row by row as laid out on the keypad (123C 456D 789E A0BF)
+    #[arg(long, default_value = DEFAULT_KEYMAP, value_parser = Keymap::parse)]
+    pub keymap: Keymap,
+    /// Display colours: a preset (default, amber, green, lcd) or 2 or 4 comma-separated RRGGBB values
+    #[arg(long, default_value = DEFAULT_PALETTE, value_parser = Palette::parse)]
+    pub palette: Palette,
+    /// Pixel size of screenshots, as a multiple of the native resolution
+    #[arg(long, default_value_t = SCALE_FACTOR)]
+    pub screenshot_scale: u32,
+    /// Target platform: chip8, schip, xochip or megachip
+    #[arg(long, default_value_t = Platform::Chip8)]
+    pub platform: Platform,
+    /// Quirks preset (vip, chip48, schip) or comma-separated quirks, remembered per ROM
+    #[arg(long)]
+    pub quirks: Option<Quirks>,
+    /// Seed for the RND instruction, making runs reproducible; random if omitted
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Pitch of the beep in Hz
+    #[arg(long, default_value_t = DEFAULT_FREQUENCY)]
+    pub frequency: f32,
+    /// Beep volume between 0.0 and 1.0
+    #[arg(long, default_value_t = DEFAULT_VOLUME)]
+    pub volume: f32,
+    #[arg(long)]
+    pub mute: bool,
+    /// Write the beeper output to a WAV file instead of the sound card
+    #[arg(long)]
+    pub audio_out: Option<String>,
+    /// Record the keypad input of every frame to a movie file
+    #[arg(long)]
+    pub record: Option<String>,
+    /// Replay the input recorded in a movie file
+    #[arg(long)]
+    pub play: Option<String>,
+    /// How many seconds of gameplay the rewind key can step back through
+    #[arg(long, default_value_t = DEFAULT_REWIND_SECONDS)]
+    pub rewind_seconds: u32,
+    /// Memory budget for the rewind history in MiB
+    #[arg(long, default_value_t = DEFAULT_REWIND_MEMORY / (1024 * 1024))]
+    pub rewind_memory: usize,
+    /// Record the display from the first frame to a .gif, .y4m or .rgb file, or `-` for Y4M on stdout (headless only)
+    #[arg(long)]
+    pub video: Option<String>,
+    /// Format of the clips started with the video key: gif, y4m or rgb
+    #[arg(long, default_value = "gif")]
+    pub video_format: VideoFormat,
+    /// Pixel size of recorded video, as a multiple of the platform's largest resolution
+    #[arg(long, default_value_t = 4)]
+    pub video_scale: u32,
+}
+
+#[derive(Parser)]
+pub struct StartCommand {
+    /// ROM or Octo source (.8o) to run instead of choosing from the game list
+    #[arg(short, long)]
+    pub rom: Option<String>,
+    /// Listen for a GDB remote protocol connection on this address, e.g. 127.0.0.1:1234
+    #[arg(long)]
+    pub gdb: Option<String>,
+    #[command(flatten)]
+    pub options: EmulatorOptions,
+}
+
+#[derive(Parser)]
+pub struct DisasmCommand {
+    pub rom: String,
+    /// Instruction set used to decode the ROM
+    #[arg(long, default_value_t = Platform::Chip8)]
+    pub platform: Platform,
+    /// Name jump, call and sprite targets and print re-assemblable source
+    #[arg(short, long)]
+    pub labels: bool,
+}
+
+#[derive(Parser)]
+pub struct CompileCommand {
+    pub source: String,
+    /// Path of the ROM to write
+    #[arg(short, long)]
+    pub output: String,
+}
+
+#[derive(Parser)]
+pub struct AsmCommand {
+    pub source: String,
+    /// Path of the ROM to write
+    #[arg(short, long)]
+    pub output: String,
 }
 
 #[derive(Parser)]
@@ -18,3 +124,20 @@ pub struct AddCommand {
     #[arg(short, long)]
     pub game: String,
 }
+
+#[derive(Parser)]
+pub struct RunCommand {
+    #[arg(short, long)]
+    pub rom: String,
+    #[arg(long)]
+    pub headless: bool,
+    #[arg(short, long, default_value_t = 1000)]
+    pub cycles: u64,
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// Save the final display as a PNG; a directory or `{rom}`/`{frame}` in the name give per-run file names
+    #[arg(long)]
+    pub screenshot: Option<String>,
+    #[command(flatten)]
+    pub options: EmulatorOptions,
+}
@@ -1,6 +1,11 @@
-use crate::cli::AddCommand;
+use crate::cli::{AddCommand, AsmCommand, CompileCommand, DisasmCommand, EmulatorOptions, RunCommand, StartCommand};
 use crate::emulator::Emulator;
+use crate::gdb::GdbServer;
+use crate::headless;
+use chip8_emulator::{assembler, disassembler, octo};
 use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
 use winit::event_loop::EventLoop;
 
 pub fn handle_add_command(add_command: &AddCommand) {
@@ -16,13 +21,96 @@ pub fn handle_add_command(add_command: &AddCommand) {
     }
 }
 
-pub fn handle_start_command() {
+pub fn handle_start_command(start_command: &StartCommand) {
+    let game = match &start_command.rom {
+        Some(rom) => rom.clone(),
+        None => match Emulator::select_game() {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Exiting with error: {}", e);
+                return;
+            }
+        },
+    };
+
+    let gdb = match start_command.gdb.as_deref().map(GdbServer::bind).transpose() {
+        Ok(gdb) => gdb,
+        Err(e) => {
+            eprintln!("Failed to start GDB server: {}", e);
+            return;
+        }
+    };
+    if let Some(address) = gdb.as_ref().and_then(|gdb| gdb.local_addr().ok()) {
+        println!("Waiting for GDB on {}", address);
+    }
+
+    start_emulator(&game, &start_command.options, gdb);
+}
+
+pub fn handle_run_command(run_command: &RunCommand) -> ExitCode {
+    if !run_command.headless {
+        start_emulator(&run_command.rom, &run_command.options, None);
+        return ExitCode::SUCCESS;
+    }
+
+    headless::run(run_command)
+}
+
+pub fn handle_disasm_command(disasm_command: &DisasmCommand) -> ExitCode {
+    match fs::read(&disasm_command.rom) {
+        Ok(rom) => {
+            print!("{}", disassembler::disassemble(&rom, disasm_command.platform, disasm_command.labels));
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", disasm_command.rom, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+pub fn handle_asm_command(asm_command: &AsmCommand) -> ExitCode {
+    let rom = match assembler::assemble_file(Path::new(&asm_command.source)) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    write_rom(&asm_command.output, &rom)
+}
+
+pub fn handle_compile_command(compile_command: &CompileCommand) -> ExitCode {
+    match octo::compile_file(Path::new(&compile_command.source)) {
+        Ok(program) => write_rom(&compile_command.output, &program.rom),
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn write_rom(path: &str, rom: &[u8]) -> ExitCode {
+    match fs::write(path, rom) {
+        Ok(_) => {
+            println!("Wrote {} bytes to {}", rom.len(), path);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn start_emulator(game: &str, options: &EmulatorOptions, gdb: Option<GdbServer>) {
     println!("Starting...");
 
     let event_loop = EventLoop::new();
-    let mut emulator = Emulator::new();
+    let mut emulator = Emulator::new(options, gdb);
 
-    match emulator.start(event_loop) {
+    match emulator.start(event_loop, game) {
         Ok(_) => println!("Shutting down..."),
         Err(e) => eprintln!("Exiting with error: {}", e),
     }
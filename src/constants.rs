@@ -1,3 +1,10 @@
 pub const CHIP8_WIDTH: u32 = 64;
 pub const CHIP8_HEIGHT: u32 = 32;
+pub const SCHIP_WIDTH: u32 = 128;
+pub const SCHIP_HEIGHT: u32 = 64;
+pub const MEGA_WIDTH: u32 = 256;
+pub const MEGA_HEIGHT: u32 = 192;
 pub const SCALE_FACTOR: u32 = 10;
+pub const TIMER_FREQUENCY: u32 = 60;
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 11;
+pub const SAVES_DIR: &str = "./saves";
@@ -1,35 +1,132 @@
 use std::fs::File;
 use std::io;
 use std::io::Read;
-use rand::random;
-use crate::opcode::Opcode;
-use crate::constants::{CHIP8_WIDTH, CHIP8_HEIGHT};
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+use std::sync::Arc;
+use crate::audio::{AudioPattern, AudioState, DigitalSound, AUDIO_PATTERN_SIZE, DEFAULT_PITCH};
+use crate::debug::{Access, DebugState, Register, StopReason, Watchpoint};
+use crate::error::Chip8Error;
+use crate::font::{
+    BIG_FONT_ADDRESS, BIG_FONT_GLYPH_SIZE, BIG_FONT_SET, FONT_ADDRESS, FONT_GLYPH_SIZE, FONT_SET,
+};
+use crate::octo::{self, SourceLocation, SourceMap};
+use crate::opcode::{self as decoder, Opcode};
+use crate::platform::Platform;
+use crate::quirks::{Quirks, QUIRK_COUNT};
+use crate::rng::Rng;
+use crate::rom::rom_hash;
+use crate::savestate::{self, StateReader, StateWriter, SAVE_STATE_VERSION};
+use crate::constants::{
+    CHIP8_WIDTH, CHIP8_HEIGHT, MEGA_HEIGHT, MEGA_WIDTH, SCHIP_WIDTH, SCHIP_HEIGHT,
+};
 
 const PROGRAM_START: u16 = 0x200;
-const MEMORY_SIZE: usize = 4096;
 const NUM_REGISTERS: usize = 16;
 const STACK_SIZE: usize = 16;
-const MAX_PROGRAM_SIZE: usize = MEMORY_SIZE - PROGRAM_START as usize;
 const NUM_KEYS: usize = 16;
+const NUM_RPL_FLAGS: usize = 16;
+const DISPLAY_SIZE: usize = (MEGA_WIDTH * MEGA_HEIGHT) as usize;
+// Granularity of memory write tracking used by the rewind buffer.
+pub(crate) const MEMORY_PAGE_SIZE: usize = 256;
+const PALETTE_SIZE: usize = 256;
+const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MemoryAccess {
+    Wrap,
+    #[default]
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum BlendMode {
+    #[default]
+    Normal,
+    Percent25,
+    Percent50,
+    Add,
+    Multiply,
+}
+
+#[derive(Clone, Copy)]
+struct KeyWait {
+    register: u8,
+    key: Option<usize>,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone)]
 pub struct CPU {
-    memory: [u8; MEMORY_SIZE],
+    memory: Vec<u8>,
+    platform: Platform,
     v: [u8; NUM_REGISTERS],
-    i: u16,
+    i: u32,
     pc: u16,
     stack: [u16; STACK_SIZE],
     sp: u8,
     delay_timer: u8,
     sound_timer: u8,
-    pub display: [bool; (CHIP8_WIDTH * CHIP8_HEIGHT) as usize],
+    display: [u8; DISPLAY_SIZE],
+    planes: u8,
+    hires: bool,
+    halted: bool,
+    rpl: [u8; NUM_RPL_FLAGS],
     keys: [bool; NUM_KEYS],
+    current_pc: u16,
+    current_opcode: u16,
+    memory_access: MemoryAccess,
+    font_address: u16,
+    key_wait: Option<KeyWait>,
+    quirks: Quirks,
+    vblank_wait: bool,
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pitch: u8,
+    pattern_loaded: bool,
+    mega: bool,
+    palette: [[u8; 4]; PALETTE_SIZE],
+    mega_frame: Vec<[u8; 4]>,
+    sprite_width: usize,
+    sprite_height: usize,
+    alpha: u8,
+    blend_mode: BlendMode,
+    collision_color: u8,
+    digital_sound: Option<DigitalSound>,
+    rom_hash: u64,
+    source_map: Option<Arc<SourceMap>>,
+    debug: DebugState,
+    rng: Rng,
+    // Every memory write bumps `memory_writes` and stamps its page with the new count, so
+    // the rewind buffer can find the pages changed since its last snapshot.
+    memory_writes: u64,
+    page_writes: Vec<u64>,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
     pub fn new() -> CPU {
-        CPU {
-            memory: [0; MEMORY_SIZE],
+        Self::with_platform(Platform::default())
+    }
+
+    // RND is seeded from system entropy; use `with_seed` for reproducible runs.
+    pub fn with_platform(platform: Platform) -> CPU {
+        Self::with_rng(platform, Rng::from_entropy())
+    }
+
+    pub fn with_seed(platform: Platform, seed: u64) -> CPU {
+        Self::with_rng(platform, Rng::new(seed))
+    }
+
+    fn with_rng(platform: Platform, rng: Rng) -> CPU {
+        let mut cpu = CPU {
+            memory: vec![0; platform.memory_size()],
+            platform,
             v: [0; NUM_REGISTERS],
             i: 0,
             pc: PROGRAM_START,
@@ -37,125 +134,792 @@ impl CPU {
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
-            display: [false; (CHIP8_WIDTH * CHIP8_HEIGHT) as usize],
+            display: [0; DISPLAY_SIZE],
+            planes: 0b01,
+            hires: false,
+            halted: false,
+            rpl: [0; NUM_RPL_FLAGS],
             keys: [false; NUM_KEYS],
-        }
+            current_pc: PROGRAM_START,
+            current_opcode: 0,
+            memory_access: MemoryAccess::default(),
+            font_address: FONT_ADDRESS,
+            key_wait: None,
+            quirks: Quirks::default(),
+            vblank_wait: false,
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
+            pattern_loaded: false,
+            mega: false,
+            palette: [WHITE; PALETTE_SIZE],
+            mega_frame: Vec::new(),
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend_mode: BlendMode::default(),
+            collision_color: 0,
+            digital_sound: None,
+            rom_hash: rom_hash(&[]),
+            source_map: None,
+            debug: DebugState::default(),
+            rng,
+            memory_writes: 0,
+            page_writes: vec![0; platform.memory_size().div_ceil(MEMORY_PAGE_SIZE)],
+        };
+        cpu.palette[0] = BLACK;
+        cpu.memory[FONT_ADDRESS as usize..FONT_ADDRESS as usize + FONT_SET.len()].copy_from_slice(&FONT_SET);
+        cpu.memory[BIG_FONT_ADDRESS as usize..BIG_FONT_ADDRESS as usize + BIG_FONT_SET.len()]
+            .copy_from_slice(&BIG_FONT_SET);
+        cpu
     }
 
+    // Octo sources (`.8o`) are compiled on the fly and keep a source map for error reporting.
     pub fn load_to_memory(&mut self, filename: &str) -> Result<(), io::Error> {
+        let path = Path::new(filename);
+        if path.extension().is_some_and(|extension| extension == "8o") {
+            let program = octo::compile_file(path)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.load_rom(&program.rom)?;
+            self.source_map = Some(Arc::new(program.source_map));
+            return Ok(());
+        }
+
         let mut file = File::open(filename)?;
         let mut buffer = Vec::new();
 
         file.read_to_end(&mut buffer)?;
 
-        if buffer.len() > MAX_PROGRAM_SIZE {
+        self.load_rom(&buffer)
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), io::Error> {
+        if rom.len() > self.memory.len() - PROGRAM_START as usize {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "File too large to read",
             ));
         }
-        self.memory[PROGRAM_START as usize..(PROGRAM_START as usize + buffer.len())].copy_from_slice(&buffer);
+        self.memory[PROGRAM_START as usize..(PROGRAM_START as usize + rom.len())].copy_from_slice(rom);
+        self.mark_written(PROGRAM_START as usize..PROGRAM_START as usize + rom.len());
+        self.rom_hash = rom_hash(rom);
+        self.source_map = None;
+
+        Ok(())
+    }
+
+    pub fn load_font(&mut self, font: &[u8], address: u16) -> Result<(), io::Error> {
+        if font.len() != FONT_SET.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Font must be exactly {} bytes", FONT_SET.len()),
+            ));
+        }
+        let start = address as usize;
+        let end = start + font.len();
+        if end > PROGRAM_START as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Font overlaps program memory",
+            ));
+        }
+        self.memory[start..end].copy_from_slice(font);
+        self.mark_written(start..end);
+        self.font_address = address;
 
         Ok(())
     }
 
-    pub fn execute_cycle(&mut self) -> Result<(), String> {
-        let opcode = self.fetch_opcode();
-        let decoded_opcode = self.decode_opcode(opcode);
-        self.pc += 2;
-        self.execute_opcode(decoded_opcode)?;
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.key_wait.is_some() || self.vblank_wait || self.halted {
+            return Ok(());
+        }
+        self.current_pc = self.pc;
+        self.current_opcode = 0;
+        let opcode = self.fetch_opcode()?;
+        self.current_opcode = opcode;
+        let decoded_opcode = if Opcode::is_long(opcode, self.platform) {
+            self.pc = self.pc.wrapping_add(2);
+            let operand = self.fetch_opcode()?;
+            Opcode::decode_long(opcode, operand)
+        } else {
+            Opcode::decode(opcode, self.platform)
+        };
+        self.pc = self.pc.wrapping_add(2);
+        self.execute_opcode(decoded_opcode)
+    }
 
+    pub fn tick_timers(&mut self) {
+        self.vblank_wait = false;
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
+    }
+
+    pub fn run_frame(&mut self, cycles_per_frame: u32) -> Result<(), Chip8Error> {
+        for _ in 0..cycles_per_frame {
+            self.step()?;
+        }
+        self.tick_timers();
 
         Ok(())
     }
 
     pub fn set_key(&mut self, key: usize, pressed: bool) {
-        if key < NUM_KEYS {
-            self.keys[key] = pressed;
+        if key >= NUM_KEYS {
+            return;
+        }
+        let was_pressed = self.keys[key];
+        self.keys[key] = pressed;
+
+        if let Some(wait) = self.key_wait {
+            match wait.key {
+                None if pressed && !was_pressed => {
+                    self.key_wait = Some(KeyWait { key: Some(key), ..wait });
+                }
+                Some(waited) if waited == key && !pressed => {
+                    self.v[wait.register as usize] = key as u8;
+                    self.key_wait = None;
+                }
+                _ => (),
+            }
         }
     }
 
-    fn fetch_opcode(&self) -> u16 {
-        let high_byte = self.memory[self.pc as usize] as u16;
-        let low_byte = self.memory[(self.pc + 1) as usize] as u16;
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
 
-        (high_byte << 8) | low_byte
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
     }
 
-    fn decode_opcode(&self, opcode: u16) -> Opcode {
-        let x = ((opcode & 0x0F00) >> 8) as u8;
-        let y = ((opcode & 0x00F0) >> 4) as u8;
-        let nnn = opcode & 0x0FFF;
-        let kk = (opcode & 0x00FF) as u8;
-        let n = (opcode & 0x000F) as u8;
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_memory_access(&mut self, memory_access: MemoryAccess) {
+        self.memory_access = memory_access;
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    // Each pixel holds one bit per bitplane, so plain CHIP-8 and SUPER-CHIP ROMs only produce 0 or 1.
+    pub fn display(&self) -> &[u8] {
+        &self.display[..self.display_width() * self.display_height()]
+    }
 
-        match opcode & 0xF000 {
-            0x0000 => match opcode {
-                0x00E0 => Opcode::CLS,
-                0x00EE => Opcode::RET,
-                _ => Opcode::SYS(nnn),
+    pub fn display_width(&self) -> usize {
+        if self.mega {
+            MEGA_WIDTH as usize
+        } else if self.hires {
+            SCHIP_WIDTH as usize
+        } else {
+            CHIP8_WIDTH as usize
+        }
+    }
+
+    pub fn display_height(&self) -> usize {
+        if self.mega {
+            MEGA_HEIGHT as usize
+        } else if self.hires {
+            SCHIP_HEIGHT as usize
+        } else {
+            CHIP8_HEIGHT as usize
+        }
+    }
+
+    // In MegaChip mode `display()` holds palette indices and this is the blended RGBA image.
+    pub fn mega_frame(&self) -> Option<&[[u8; 4]]> {
+        self.mega.then_some(&self.mega_frame[..])
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.pattern_loaded.then_some(AudioPattern {
+            buffer: self.audio_pattern,
+            pitch: self.pitch,
+        })
+    }
+
+    pub fn audio_state(&self) -> AudioState {
+        AudioState {
+            beeping: self.sound_timer > 0,
+            pattern: self.audio_pattern(),
+            digital: self.digital_sound.clone(),
+        }
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.v
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        if let Some(register) = self.v.get_mut(x) {
+            *register = value;
+        }
+    }
+
+    pub fn i(&self) -> u32 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u32) {
+        self.i = i;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: u8) {
+        self.sp = sp.min(STACK_SIZE as u8);
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn set_stack_entry(&mut self, index: usize, address: u16) {
+        if let Some(entry) = self.stack.get_mut(index) {
+            *entry = address;
+        }
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn keys(&self) -> &[bool] {
+        &self.keys
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn set_memory(&mut self, address: usize, value: u8) {
+        if let Some(byte) = self.memory.get_mut(address) {
+            *byte = value;
+            self.mark_written(address..address + 1);
+        }
+    }
+
+    // Hash of the ROM image as loaded, before the program gets a chance to modify itself.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    // Serialises the whole machine into the versioned, compressed save-state format.
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::encode(self.rom_hash, &self.snapshot())
+    }
+
+    // Restores a state written by `save_state`. States taken from a different ROM are
+    // refused, and the CPU is left untouched if the state cannot be read.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let (header, state) = savestate::decode(data)?;
+        if header.rom_hash != self.rom_hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Save state was made for a different ROM"));
+        }
+        self.restore(&state, header.version)
+    }
+
+    // The uncompressed machine state shared by save states and the rewind buffer.
+    pub(crate) fn snapshot(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        state.u8(self.platform.id());
+        state.blob(&self.memory);
+        self.write_machine(&mut state);
+        state.data
+    }
+
+    // Everything but memory, which the rewind buffer tracks page by page instead.
+    pub(crate) fn machine_snapshot(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        state.u8(self.platform.id());
+        self.write_machine(&mut state);
+        state.data
+    }
+
+    fn write_machine(&self, state: &mut StateWriter) {
+        state.bytes(&self.v);
+        state.u32(self.i);
+        state.u16(self.pc);
+        for &address in &self.stack {
+            state.u16(address);
+        }
+        state.u8(self.sp);
+        state.u8(self.delay_timer);
+        state.u8(self.sound_timer);
+        state.bytes(&self.display);
+        state.u8(self.planes);
+        state.bool(self.hires);
+        state.bool(self.halted);
+        state.bytes(&self.rpl);
+        for &pressed in &self.keys {
+            state.bool(pressed);
+        }
+        state.u16(self.current_pc);
+        state.u16(self.current_opcode);
+        state.bool(self.memory_access == MemoryAccess::Wrap);
+        state.u16(self.font_address);
+        match self.key_wait {
+            // 0xFF: waiting for a key to go down; 0xFE: not waiting.
+            Some(wait) => {
+                state.u8(wait.register);
+                state.u8(wait.key.map_or(0xFF, |key| key as u8));
+            }
+            None => {
+                state.u8(0xFE);
+                state.u8(0xFF);
+            }
+        }
+        state.u8(self.quirks.bits());
+        state.bool(self.vblank_wait);
+        state.bytes(&self.audio_pattern);
+        state.u8(self.pitch);
+        state.bool(self.pattern_loaded);
+        state.bool(self.mega);
+        state.bytes(self.palette.as_flattened());
+        state.blob(self.mega_frame.as_flattened());
+        state.u32(self.sprite_width as u32);
+        state.u32(self.sprite_height as u32);
+        state.u8(self.alpha);
+        state.u8(self.blend_mode as u8);
+        state.u8(self.collision_color);
+        state.bool(self.digital_sound.is_some());
+        if let Some(sound) = &self.digital_sound {
+            state.u32(sound.sample_rate);
+            state.blob(&sound.samples);
+            state.bool(sound.looping);
+        }
+        state.u64(self.rng.state());
+    }
+
+    pub(crate) fn restore(&mut self, state: &[u8], version: u16) -> Result<(), io::Error> {
+        let mut state = StateReader::new(state);
+        let platform = Platform::from_id(state.u8()?).ok_or_else(|| savestate::invalid("unknown platform"))?;
+        let memory = state.blob()?;
+        if memory.len() != platform.memory_size() {
+            return Err(savestate::invalid("memory size does not match the platform"));
+        }
+
+        let mut cpu = self.read_machine(platform, &mut state, version)?;
+        cpu.memory = memory.to_vec();
+        cpu.page_writes = vec![0; memory.len().div_ceil(MEMORY_PAGE_SIZE)];
+        cpu.mark_written(0..memory.len());
+        *self = cpu;
+        Ok(())
+    }
+
+    // Restores a `machine_snapshot`, keeping the current memory.
+    pub(crate) fn restore_machine(&mut self, state: &[u8]) -> Result<(), io::Error> {
+        let mut state = StateReader::new(state);
+        let platform = Platform::from_id(state.u8()?).ok_or_else(|| savestate::invalid("unknown platform"))?;
+        if self.memory.len() != platform.memory_size() {
+            return Err(savestate::invalid("memory size does not match the platform"));
+        }
+
+        let mut cpu = self.read_machine(platform, &mut state, SAVE_STATE_VERSION)?;
+        cpu.memory = std::mem::take(&mut self.memory);
+        cpu.page_writes = std::mem::take(&mut self.page_writes);
+        *self = cpu;
+        Ok(())
+    }
+
+    // Reads the fields after memory into a new CPU that keeps this one's ROM and debugger
+    // state, leaving `self` untouched if the state is invalid. Fields are listed in state
+    // order; memory and its write stamps are left for the caller rather than cloned.
+    fn read_machine(&self, platform: Platform, state: &mut StateReader, version: u16) -> Result<CPU, io::Error> {
+        let cpu = CPU {
+            memory: Vec::new(),
+            platform,
+            v: state.array()?,
+            i: state.u32()?,
+            pc: state.u16()?,
+            stack: {
+                let mut stack = [0; STACK_SIZE];
+                for address in stack.iter_mut() {
+                    *address = state.u16()?;
+                }
+                stack
             },
-            0x1000 => Opcode::JP(nnn),
-            0x2000 => Opcode::CALL(nnn),
-            0x3000 => Opcode::SE_Vx_byte(x, kk),
-            0x4000 => Opcode::SNE_Vx_byte(x, kk),
-            0x5000 if n == 0 => Opcode::SE_Vx_Vy(x, y),
-            0x6000 => Opcode::LD_Vx_byte(x, kk),
-            0x7000 => Opcode::ADD_Vx_byte(x, kk),
-            0x8000 => match n {
-                0x0 => Opcode::LD_Vx_Vy(x, y),
-                0x1 => Opcode::OR_Vx_Vy(x, y),
-                0x2 => Opcode::AND_Vx_Vy(x, y),
-                0x3 => Opcode::XOR_Vx_Vy(x, y),
-                0x4 => Opcode::ADD_Vx_Vy(x, y),
-                0x5 => Opcode::SUB_Vx_Vy(x, y),
-                0x6 => Opcode::SHR_Vx(x),
-                0x7 => Opcode::SUBN_Vx_Vy(x, y),
-                0xE => Opcode::SHL_Vx(x),
-                _ => Opcode::INVALID(opcode),
+            sp: state.u8()?.min(STACK_SIZE as u8),
+            delay_timer: state.u8()?,
+            sound_timer: state.u8()?,
+            display: state.array()?,
+            planes: state.u8()?,
+            hires: state.bool()?,
+            halted: state.bool()?,
+            rpl: state.array()?,
+            keys: {
+                let mut keys = [false; NUM_KEYS];
+                for pressed in keys.iter_mut() {
+                    *pressed = state.bool()?;
+                }
+                keys
+            },
+            current_pc: state.u16()?,
+            current_opcode: state.u16()?,
+            memory_access: if state.bool()? { MemoryAccess::Wrap } else { MemoryAccess::Error },
+            font_address: state.u16()?,
+            key_wait: match (state.u8()?, state.u8()?) {
+                (0xFE, _) => None,
+                (register, key) if (register as usize) < NUM_REGISTERS => Some(KeyWait {
+                    register,
+                    key: (key != 0xFF).then_some(key as usize % NUM_KEYS),
+                }),
+                _ => return Err(savestate::invalid("invalid key wait register")),
             },
-            0x9000 if n == 0 => Opcode::SNE_Vx_Vy(x, y),
-            0xA000 => Opcode::LD_I_addr(nnn),
-            0xB000 => Opcode::JP_V0_addr(nnn),
-            0xC000 => Opcode::RND_Vx_byte(x, kk),
-            0xD000 => Opcode::DRW_Vx_Vy_nibble(x, y, n),
-            0xE000 => match kk {
-                0x9E => Opcode::SKP_Vx(x),
-                0xA1 => Opcode::SKNP_Vx(x),
-                _ => Opcode::INVALID(opcode),
+            quirks: if version >= 2 {
+                Quirks::from_bits(state.u8()?)
+            } else {
+                // Version 1 stored one bool per quirk.
+                let mut quirks = 0;
+                for index in 0..QUIRK_COUNT {
+                    quirks |= (state.bool()? as u8) << index;
+                }
+                Quirks::from_bits(quirks)
             },
-            0xF000 => match kk {
-                0x07 => Opcode::LD_Vx_DT(x),
-                0x0A => Opcode::LD_Vx_K(x),
-                0x15 => Opcode::LD_DT_Vx(x),
-                0x18 => Opcode::LD_ST_Vx(x),
-                0x1E => Opcode::ADD_I_Vx(x),
-                0x29 => Opcode::LD_F_Vx(x),
-                0x33 => Opcode::LD_B_Vx(x),
-                0x55 => Opcode::LD_I_Vx(x),
-                0x65 => Opcode::LD_Vx_I(x),
-                _ => Opcode::INVALID(opcode),
+            vblank_wait: state.bool()?,
+            audio_pattern: state.array()?,
+            pitch: state.u8()?,
+            pattern_loaded: state.bool()?,
+            mega: state.bool()?,
+            palette: {
+                let mut palette = [[0; 4]; PALETTE_SIZE];
+                for color in palette.iter_mut() {
+                    *color = state.array()?;
+                }
+                palette
             },
-            _ => Opcode::INVALID(opcode),
+            mega_frame: state.blob()?.chunks_exact(4).map(|color| [color[0], color[1], color[2], color[3]]).collect(),
+            sprite_width: state.u32()? as usize,
+            sprite_height: state.u32()? as usize,
+            alpha: state.u8()?,
+            blend_mode: blend_mode_from_id(state.u8()?)?,
+            collision_color: state.u8()?,
+            digital_sound: if state.bool()? {
+                Some(DigitalSound {
+                    sample_rate: state.u32()?,
+                    samples: state.blob()?.into(),
+                    looping: state.bool()?,
+                })
+            } else {
+                None
+            },
+            rom_hash: self.rom_hash,
+            source_map: self.source_map.clone(),
+            debug: self.debug.clone(),
+            // Version 1 states predate the RNG being part of the machine state.
+            rng: if version >= 2 { Rng::from_state(state.u64()?) } else { self.rng },
+            memory_writes: self.memory_writes,
+            page_writes: Vec::new(),
+        };
+
+        // The drawing code indexes these without further checks.
+        if cpu.mega && (platform != Platform::MegaChip || cpu.mega_frame.len() != DISPLAY_SIZE) {
+            return Err(savestate::invalid("invalid MegaChip display"));
+        }
+        if cpu.sprite_width > 256 || cpu.sprite_height > 256 {
+            return Err(savestate::invalid("invalid MegaChip sprite size"));
+        }
+        Ok(cpu)
+    }
+
+    pub(crate) fn memory_writes(&self) -> u64 {
+        self.memory_writes
+    }
+
+    // Pages written to after the write count was `since`.
+    pub(crate) fn pages_written_since(&self, since: u64) -> impl Iterator<Item = usize> + '_ {
+        self.page_writes.iter().enumerate().filter(move |&(_, &written)| written > since).map(|(page, _)| page)
+    }
+
+    pub(crate) fn write_page(&mut self, page: usize, bytes: &[u8]) {
+        let start = page * MEMORY_PAGE_SIZE;
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+        self.mark_written(start..start + bytes.len());
+    }
+
+    fn mark_written(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.memory_writes += 1;
+        for page in range.start / MEMORY_PAGE_SIZE..=(range.end - 1) / MEMORY_PAGE_SIZE {
+            self.page_writes[page] = self.memory_writes;
+        }
+    }
+
+    pub fn source_location(&self, address: u16) -> Option<&SourceLocation> {
+        self.source_map.as_ref()?.lookup(address)
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.debug.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.debug.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.debug.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<usize>, access: Access) {
+        self.debug.watchpoints.push(Watchpoint { range, access });
+    }
+
+    pub fn remove_watchpoint(&mut self, range: RangeInclusive<usize>, access: Access) -> bool {
+        let count = self.debug.watchpoints.len();
+        self.debug.watchpoints.retain(|watchpoint| watchpoint.range != range || watchpoint.access != access);
+        self.debug.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.debug.watchpoints
+    }
+
+    pub fn watch_register(&mut self, register: Register) {
+        if !self.debug.registers.contains(&register) {
+            self.debug.registers.push(register);
+        }
+    }
+
+    pub fn unwatch_register(&mut self, register: Register) {
+        self.debug.registers.retain(|&watched| watched != register);
+    }
+
+    // Stops before executing any instruction with this mnemonic, e.g. "DRW".
+    pub fn break_on_opcode(&mut self, mnemonic: &str) {
+        self.debug.opcode_breaks.insert(mnemonic.to_ascii_uppercase());
+    }
+
+    pub fn clear_opcode_break(&mut self, mnemonic: &str) {
+        self.debug.opcode_breaks.remove(&mnemonic.to_ascii_uppercase());
+    }
+
+    pub fn clear_debug_points(&mut self) {
+        self.debug.breakpoints.clear();
+        self.debug.watchpoints.clear();
+        self.debug.registers.clear();
+        self.debug.opcode_breaks.clear();
+    }
+
+    // Timers tick once every `cycles` instructions while the debugging helpers run the CPU.
+    pub fn set_debug_cycles_per_frame(&mut self, cycles: u32) {
+        self.debug.cycles_per_frame = cycles.max(1);
+    }
+
+    pub fn register_value(&self, register: Register) -> u32 {
+        match register {
+            Register::V(x) => self.v[x as usize & 0xF] as u32,
+            Register::I => self.i,
+            Register::SP => self.sp as u32,
+            Register::DT => self.delay_timer as u32,
+            Register::ST => self.sound_timer as u32,
         }
     }
 
-    pub fn execute_opcode(&mut self, opcode: Opcode) -> Result<(), String> {
+    pub fn next_opcode(&self) -> Option<Opcode> {
+        decoder::decode(self.memory.get(self.pc as usize..)?, self.platform)
+    }
+
+    // Executes a single instruction, reporting any watchpoint or watched register it triggered.
+    pub fn step_instruction(&mut self) -> StopReason {
+        match self.debug_step() {
+            Ok(Some(reason)) => reason,
+            Ok(None) => StopReason::StepComplete,
+            Err(e) => StopReason::Error(e),
+        }
+    }
+
+    // Runs a CALL to completion, giving up after `max_cycles` instructions; any other
+    // instruction is single-stepped.
+    pub fn step_over(&mut self, max_cycles: u64) -> StopReason {
+        match self.next_opcode() {
+            Some(Opcode::CALL(_)) => {
+                let (return_pc, sp) = (self.pc.wrapping_add(2), self.sp);
+                match self.run_until_for(max_cycles, |cpu| cpu.pc == return_pc && cpu.sp <= sp) {
+                    StopReason::Condition => StopReason::StepComplete,
+                    reason => reason,
+                }
+            }
+            _ => self.step_instruction(),
+        }
+    }
+
+    // Runs until the current subroutine returns or `max_cycles` instructions have run.
+    // Outside a subroutine this is a single step.
+    pub fn step_out(&mut self, max_cycles: u64) -> StopReason {
+        let sp = self.sp;
+        if sp == 0 {
+            return self.step_instruction();
+        }
+        match self.run_until_for(max_cycles, |cpu| cpu.sp < sp) {
+            StopReason::Condition => StopReason::StepComplete,
+            reason => reason,
+        }
+    }
+
+    // Runs until `stop` returns true after an instruction, or a breakpoint, watchpoint
+    // or opcode break fires. The instruction at the current PC always executes, so
+    // resuming from a breakpoint doesn't stop on it again. There is no instruction limit;
+    // see `run_until_for`.
+    pub fn run_until(&mut self, stop: impl FnMut(&CPU) -> bool) -> StopReason {
+        self.run_until_for(u64::MAX, stop)
+    }
+
+    // `run_until` that gives up with `BudgetExhausted` after `max_cycles` instructions.
+    pub fn run_until_for(&mut self, max_cycles: u64, mut stop: impl FnMut(&CPU) -> bool) -> StopReason {
+        for cycle in 0..max_cycles {
+            if self.halted {
+                return StopReason::Halted;
+            }
+            if self.key_wait.is_some() {
+                return StopReason::WaitingForKey;
+            }
+            if cycle > 0 {
+                if self.debug.breakpoints.contains(&self.pc) {
+                    return StopReason::Breakpoint(self.pc);
+                }
+                if let Some(opcode) = self.next_opcode().filter(|opcode| self.debug.breaks_on(opcode)) {
+                    return StopReason::Opcode(opcode);
+                }
+            }
+
+            match self.debug_step() {
+                Ok(Some(reason)) => return reason,
+                Ok(None) => (),
+                Err(e) => return StopReason::Error(e),
+            }
+            if stop(self) {
+                return StopReason::Condition;
+            }
+        }
+        StopReason::BudgetExhausted
+    }
+
+    fn debug_step(&mut self) -> Result<Option<StopReason>, Chip8Error> {
+        let watched: Vec<(Register, u32)> =
+            self.debug.registers.iter().map(|&register| (register, self.register_value(register))).collect();
+
+        self.debug.hit.set(None);
+        let result = self.step();
+        self.debug.frame_cycles += 1;
+        if self.debug.frame_cycles >= self.debug.cycles_per_frame {
+            self.debug.frame_cycles = 0;
+            self.tick_timers();
+        }
+        result?;
+
+        if let Some(hit) = self.debug.hit.take() {
+            return Ok(Some(hit));
+        }
+        for (register, old) in watched {
+            let new = self.register_value(register);
+            if new != old {
+                return Ok(Some(StopReason::RegisterChanged { register, old, new }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn fetch_opcode(&self) -> Result<u16, Chip8Error> {
+        // Instruction fetches bypass `read_memory` so they don't trigger read watchpoints.
+        let high_byte = self.memory[self.resolve_address(self.pc as usize)?] as u16;
+        let low_byte = self.memory[self.resolve_address(self.pc as usize + 1)?] as u16;
+
+        Ok((high_byte << 8) | low_byte)
+    }
+
+    fn resolve_address(&self, address: usize) -> Result<usize, Chip8Error> {
+        match self.memory_access {
+            MemoryAccess::Wrap => Ok(address % self.memory.len()),
+            MemoryAccess::Error if address < self.memory.len() => Ok(address),
+            MemoryAccess::Error => Err(self.invalid_memory_access(address)),
+        }
+    }
+
+    fn read_memory(&self, address: usize) -> Result<u8, Chip8Error> {
+        let address = self.resolve_address(address)?;
+        self.debug.record_access(address, Access::Read);
+        Ok(self.memory[address])
+    }
+
+    fn write_memory(&mut self, address: usize, value: u8) -> Result<(), Chip8Error> {
+        let address = self.resolve_address(address)?;
+        self.debug.record_access(address, Access::Write);
+        self.memory[address] = value;
+        self.mark_written(address..address + 1);
+        Ok(())
+    }
+
+    pub fn execute_opcode(&mut self, opcode: Opcode) -> Result<(), Chip8Error> {
         match opcode {
             Opcode::CLS => self.cls(),
             Opcode::RET => self.ret(),
-            Opcode::SYS(_) => Ok(()),
+            Opcode::SCD_nibble(n) => self.scd_nibble(n),
+            Opcode::SCU_nibble(n) => self.scu_nibble(n),
+            Opcode::SCRU_nibble(n) => self.scu_nibble(n),
+            Opcode::MEGAOFF => self.megaoff(),
+            Opcode::MEGAON => self.megaon(),
+            Opcode::LDHI_I(address) => self.ldhi_i(address),
+            Opcode::LDPAL(nn) => self.ldpal(nn),
+            Opcode::SPRW(nn) => self.sprw(nn),
+            Opcode::SPRH(nn) => self.sprh(nn),
+            Opcode::ALPHA(nn) => self.set_alpha(nn),
+            Opcode::DIGISND(n) => self.digisnd(n),
+            Opcode::STOPSND => self.stopsnd(),
+            Opcode::BMODE(n) => self.bmode(n),
+            Opcode::CCOL(nn) => self.ccol(nn),
+            Opcode::SCR => self.scr(),
+            Opcode::SCL => self.scl(),
+            Opcode::EXIT => self.exit(),
+            Opcode::LOW => self.low(),
+            Opcode::HIGH => self.high(),
+            Opcode::SYS(addr) => self.sys(addr),
             Opcode::JP(addr) => self.jp(addr),
             Opcode::CALL(nnn) => self.call(nnn),
             Opcode::SE_Vx_byte(x, kk) => self.se_vx_byte(x, kk),
             Opcode::SNE_Vx_byte(x, kk) => self.sne_vx_byte(x, kk),
             Opcode::SE_Vx_Vy(x, y) => self.se_vx_vy(x, y),
+            Opcode::SAVE_Vx_Vy(x, y) => self.save_vx_vy(x, y),
+            Opcode::LOAD_Vx_Vy(x, y) => self.load_vx_vy(x, y),
             Opcode::LD_Vx_byte(x, kk) => self.ld_vx_byte(x, kk),
             Opcode::ADD_Vx_byte(x, kk) => self.add_vx_byte(x, kk),
             Opcode::LD_Vx_Vy(x, y) => self.ld_vx_vy(x, y),
@@ -164,9 +928,9 @@ impl CPU {
             Opcode::XOR_Vx_Vy(x, y) => self.xor_vx_vy(x, y),
             Opcode::ADD_Vx_Vy(x, y) => self.add_vx_vy(x, y),
             Opcode::SUB_Vx_Vy(x, y) => self.sub_vx_vy(x, y),
-            Opcode::SHR_Vx(x) => self.shr_vx(x),
+            Opcode::SHR_Vx_Vy(x, y) => self.shr_vx_vy(x, y),
             Opcode::SUBN_Vx_Vy(x, y) => self.subn_vx_vy(x, y),
-            Opcode::SHL_Vx(x) => self.shl_vx(x),
+            Opcode::SHL_Vx_Vy(x, y) => self.shl_vx_vy(x, y),
             Opcode::SNE_Vx_Vy(x, y) => self.sne_vx_vy(x, y),
             Opcode::LD_I_addr(nnn) => self.ld_i_addr(nnn),
             Opcode::JP_V0_addr(nnn) => self.jp_v0_addr(nnn),
@@ -183,42 +947,149 @@ impl CPU {
             Opcode::LD_B_Vx(x) => self.ld_b_vx(x),
             Opcode::LD_I_Vx(x) => self.ld_i_vx(x),
             Opcode::LD_Vx_I(x) => self.ld_vx_i(x),
-            Opcode::INVALID(op) => Err(format!("Invalid opcode: 0x{:04X}", op)),
+            Opcode::LD_HF_Vx(x) => self.ld_hf_vx(x),
+            Opcode::LD_R_Vx(x) => self.ld_r_vx(x),
+            Opcode::LD_Vx_R(x) => self.ld_vx_r(x),
+            Opcode::LD_I_long(nnnn) => self.ld_i_long(nnnn),
+            Opcode::PLANE(n) => self.plane(n),
+            Opcode::AUDIO => self.audio(),
+            Opcode::PITCH_Vx(x) => self.pitch_vx(x),
+            Opcode::INVALID(op) => Err(Chip8Error::InvalidOpcode {
+                pc: self.current_pc,
+                opcode: op,
+            }),
         }
     }
 
-    fn cls(&mut self) -> Result<(), String> {
-        self.display = [false; (CHIP8_WIDTH * CHIP8_HEIGHT) as usize];
+    fn check_register(&self, x: u8) -> Result<(), Chip8Error> {
+        if x as usize >= NUM_REGISTERS {
+            return Err(Chip8Error::InvalidRegister {
+                pc: self.current_pc,
+                opcode: self.current_opcode,
+                register: x,
+            });
+        }
         Ok(())
     }
 
-    fn ret(&mut self) -> Result<(), String> {
+    fn invalid_memory_access(&self, address: usize) -> Chip8Error {
+        Chip8Error::InvalidMemoryAccess {
+            pc: self.current_pc,
+            opcode: self.current_opcode,
+            address,
+        }
+    }
+
+    fn cls(&mut self) -> Result<(), Chip8Error> {
+        let planes = self.plane_mask();
+        self.display.iter_mut().for_each(|pixel| *pixel &= !planes);
+        self.mega_frame.fill(BLACK);
+        Ok(())
+    }
+
+    fn plane_mask(&self) -> u8 {
+        if self.mega { 0xFF } else { self.planes }
+    }
+
+    fn scd_nibble(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.scroll(0, n as isize);
+        Ok(())
+    }
+
+    fn scu_nibble(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.scroll(0, -(n as isize));
+        Ok(())
+    }
+
+    fn scr(&mut self) -> Result<(), Chip8Error> {
+        self.scroll(4, 0);
+        Ok(())
+    }
+
+    fn scl(&mut self) -> Result<(), Chip8Error> {
+        self.scroll(-4, 0);
+        Ok(())
+    }
+
+    // Moves the selected bitplanes by (dx, dy), filling the uncovered area with blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.display_width() as isize;
+        let height = self.display_height() as isize;
+        let planes = self.plane_mask();
+        let source = self.display;
+        let source_frame = self.mega_frame.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - dx, y - dy);
+                let pixel = if (0..width).contains(&source_x) && (0..height).contains(&source_y) {
+                    source[(source_y * width + source_x) as usize] & planes
+                } else {
+                    0
+                };
+                let index = (y * width + x) as usize;
+                self.display[index] = (self.display[index] & !planes) | pixel;
+                if self.mega {
+                    self.mega_frame[index] = if pixel != 0 {
+                        source_frame[(source_y * width + source_x) as usize]
+                    } else {
+                        BLACK
+                    };
+                }
+            }
+        }
+    }
+
+    fn exit(&mut self) -> Result<(), Chip8Error> {
+        self.halted = true;
+        Ok(())
+    }
+
+    fn low(&mut self) -> Result<(), Chip8Error> {
+        self.hires = false;
+        self.display = [0; DISPLAY_SIZE];
+        Ok(())
+    }
+
+    fn high(&mut self) -> Result<(), Chip8Error> {
+        self.hires = true;
+        self.display = [0; DISPLAY_SIZE];
+        Ok(())
+    }
+
+    fn ret(&mut self) -> Result<(), Chip8Error> {
         if self.sp == 0 {
-            return Err("Stack underflow".to_string());
+            return Err(Chip8Error::StackUnderflow {
+                pc: self.current_pc,
+                opcode: self.current_opcode,
+            });
         }
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
         Ok(())
     }
 
-    fn sys(&mut self, addr: u16) -> Result<(), String> {
+    fn sys(&mut self, _addr: u16) -> Result<(), Chip8Error> {
         Ok(())
     }
 
-    fn jp(&mut self, addr: u16) -> Result<(), String> {
-        if addr >= MEMORY_SIZE as u16 {
-            return Err(format!("Invalid address for JP: 0x{:04X}", addr));
+    fn jp(&mut self, addr: u16) -> Result<(), Chip8Error> {
+        if addr as usize >= self.memory.len() {
+            return Err(self.invalid_memory_access(addr as usize));
         }
         self.pc = addr;
         Ok(())
     }
 
-    fn call(&mut self, nnn: u16) -> Result<(), String> {
+    fn call(&mut self, nnn: u16) -> Result<(), Chip8Error> {
         if self.sp as usize == STACK_SIZE {
-            return Err("Stack overflow".to_string());
+            return Err(Chip8Error::StackOverflow {
+                pc: self.current_pc,
+                opcode: self.current_opcode,
+            });
         }
-        if nnn >= MEMORY_SIZE as u16 {
-            return Err(format!("Invalid address for CALL: 0x{:04X}", nnn));
+        if nnn as usize >= self.memory.len() {
+            return Err(self.invalid_memory_access(nnn as usize));
         }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
@@ -226,291 +1097,714 @@ impl CPU {
         Ok(())
     }
 
-    fn se_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
+    fn se_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
         if self.v[x as usize] == kk {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
         Ok(())
     }
 
-    fn sne_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
+    fn sne_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
         if self.v[x as usize] != kk {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
         Ok(())
     }
 
-    fn se_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
-        }
+    fn se_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         if self.v[x as usize] == self.v[y as usize] {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
         Ok(())
     }
 
-    fn ld_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
+    fn ld_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
         self.v[x as usize] = kk;
         Ok(())
     }
 
-    fn add_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
+    fn add_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
         self.v[x as usize] = self.v[x as usize].wrapping_add(kk);
         Ok(())
     }
 
-    fn ld_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
-        }
+    fn ld_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         self.v[x as usize] = self.v[y as usize];
         Ok(())
     }
 
-    fn or_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
-        }
+    fn or_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         self.v[x as usize] |= self.v[y as usize];
+        self.reset_vf();
         Ok(())
     }
 
-    fn and_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
-        }
+    fn and_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         self.v[x as usize] &= self.v[y as usize];
+        self.reset_vf();
         Ok(())
     }
 
-    fn xor_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
-        }
+    fn xor_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         self.v[x as usize] ^= self.v[y as usize];
+        self.reset_vf();
         Ok(())
     }
 
-    fn add_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
-        }
+    fn add_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         let (sum, overflow) = self.v[x as usize].overflowing_add(self.v[y as usize]);
         self.v[x as usize] = sum;
         self.v[0xF] = if overflow { 1 } else { 0 };
         Ok(())
     }
 
-    fn sub_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
-        }
+    fn sub_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         let (diff, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
         self.v[x as usize] = diff;
         self.v[0xF] = if borrow { 0 } else { 1 };
         Ok(())
     }
 
-    fn shr_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
-        self.v[0xF] = self.v[x as usize] & 0x1;
-        self.v[x as usize] >>= 1;
+    fn shr_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
+        let value = self.shift_source(x, y);
+        self.v[x as usize] = value >> 1;
+        self.v[0xF] = value & 0x1;
         Ok(())
     }
 
-    fn subn_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
-        }
+    fn subn_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         let (diff, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
         self.v[x as usize] = diff;
         self.v[0xF] = if borrow { 0 } else { 1 };
         Ok(())
     }
 
-    fn shl_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
-        self.v[0xF] = (self.v[x as usize] & 0x80) >> 7;
-        self.v[x as usize] <<= 1;
+    fn shl_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
+        let value = self.shift_source(x, y);
+        self.v[x as usize] = value << 1;
+        self.v[0xF] = (value & 0x80) >> 7;
         Ok(())
     }
 
-    fn sne_vx_vy(&mut self, x: u8, y: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v[y as usize]
+        } else {
+            self.v[x as usize]
+        }
+    }
+
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
         }
+    }
+
+    fn sne_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
         if self.v[x as usize] != self.v[y as usize] {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
         Ok(())
     }
 
-    fn ld_i_addr(&mut self, nnn: u16) -> Result<(), String> {
-        self.i = nnn;
+    fn ld_i_addr(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+        self.i = nnn as u32;
         Ok(())
     }
 
-    fn jp_v0_addr(&mut self, nnn: u16) -> Result<(), String> {
-        self.pc = nnn + self.v[0] as u16;
+    fn jp_v0_addr(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+        let register = if self.quirks.jump_with_vx { (nnn >> 8) as usize } else { 0 };
+        self.pc = nnn + self.v[register] as u16;
         Ok(())
     }
 
-    fn rnd_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
-        let random_byte: u8 = random();
+    fn rnd_vx_byte(&mut self, x: u8, kk: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        let random_byte = self.rng.next_u8();
         self.v[x as usize] = random_byte & kk;
         Ok(())
     }
 
-    fn drw_vx_vy_nibble(&mut self, x: u8, y: u8, n: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS || y as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: x={}, y={}", x, y));
+    fn drw_vx_vy_nibble(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
+        if self.mega {
+            return self.draw_mega_sprite(x, y);
         }
-        let x_coord = self.v[x as usize] as usize;
-        let y_coord = self.v[y as usize] as usize;
+        let width = self.display_width();
+        let height = self.display_height();
+        let x_coord = self.v[x as usize] as usize % width;
+        let y_coord = self.v[y as usize] as usize % height;
+        let clip = self.quirks.clip_sprites;
+        // Dxy0 draws a 16x16 sprite stored as two bytes per row from SUPER-CHIP on; plain
+        // CHIP-8 draws zero rows.
+        let (sprite_width, sprite_height) = match (n, self.platform) {
+            (0, Platform::SuperChip | Platform::XoChip | Platform::MegaChip) => (16, 16),
+            _ => (8, n as usize),
+        };
+        let bytes_per_row = sprite_width / 8;
+        let plane_size = bytes_per_row * sprite_height;
         self.v[0xF] = 0;
 
-        for byte_index in 0..n as usize {
-            let y = (y_coord + byte_index) % CHIP8_HEIGHT as usize;
-            let sprite_byte = self.memory[(self.i as usize) + byte_index];
+        // Each selected bitplane consumes its own copy of the sprite data, one after the other.
+        let mut address = self.i as usize;
+        for plane in [0b01, 0b10] {
+            if self.planes & plane == 0 {
+                continue;
+            }
 
-            for bit_index in 0..8 {
-                let x = (x_coord + bit_index) % CHIP8_WIDTH as usize;
-                let color = (sprite_byte & (0x80 >> bit_index)) != 0;
-                let index = y * CHIP8_WIDTH as usize + x;
+            for row_index in 0..sprite_height {
+                if clip && y_coord + row_index >= height {
+                    break;
+                }
+                let y = (y_coord + row_index) % height;
+                let mut sprite_row = 0u16;
+                for byte_index in 0..bytes_per_row {
+                    let byte = self.read_memory(address + row_index * bytes_per_row + byte_index)?;
+                    sprite_row = (sprite_row << 8) | byte as u16;
+                }
 
-                if color && self.display[index] {
-                    self.v[0xF] = 1;
+                for bit_index in 0..sprite_width {
+                    if clip && x_coord + bit_index >= width {
+                        break;
+                    }
+                    let x = (x_coord + bit_index) % width;
+                    let color = (sprite_row & (1 << (sprite_width - 1 - bit_index))) != 0;
+                    let index = y * width + x;
+
+                    if color {
+                        if self.display[index] & plane != 0 {
+                            self.v[0xF] = 1;
+                        }
+                        self.display[index] ^= plane;
+                    }
                 }
-                self.display[index] ^= color;
             }
+            address += plane_size;
         }
+        self.vblank_wait = self.quirks.display_wait;
         Ok(())
     }
 
-    fn skp_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
-        if self.keys[self.v[x as usize] as usize] {
-            self.pc += 2;
+    fn skp_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        if self.keys[(self.v[x as usize] & 0xF) as usize] {
+            self.skip_next_instruction();
         }
         Ok(())
     }
 
-    fn sknp_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
-        if !self.keys[self.v[x as usize] as usize] {
-            self.pc += 2;
+    fn sknp_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        if !self.keys[(self.v[x as usize] & 0xF) as usize] {
+            self.skip_next_instruction();
         }
         Ok(())
     }
 
-    fn ld_vx_dt(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
+    fn ld_vx_dt(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
         self.v[x as usize] = self.delay_timer;
         Ok(())
     }
 
-    fn ld_vx_k(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
-        if let Some(key) = self.keys.iter().position(|&k| k) {
-            self.v[x as usize] = key as u8;
-        } else {
-            self.pc -= 2;
-        }
+    fn ld_vx_k(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.key_wait = Some(KeyWait { register: x, key: None });
         Ok(())
     }
 
-    fn ld_b_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
+    fn ld_b_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
         let vx = self.v[x as usize];
-        self.memory[self.i as usize] = vx / 100;
-        self.memory[(self.i + 1) as usize] = (vx / 10) % 10;
-        self.memory[(self.i + 2) as usize] = vx % 10;
+        self.write_memory(self.i as usize, vx / 100)?;
+        self.write_memory(self.i as usize + 1, (vx / 10) % 10)?;
+        self.write_memory(self.i as usize + 2, vx % 10)?;
         Ok(())
     }
 
-    fn ld_dt_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
+    fn ld_dt_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
         self.delay_timer = self.v[x as usize];
         Ok(())
     }
 
-    fn ld_st_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
-        }
+    fn ld_st_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
         self.sound_timer = self.v[x as usize];
         Ok(())
     }
 
-    fn add_i_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
+    fn add_i_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.i = self.i.wrapping_add(self.v[x as usize] as u32);
+        Ok(())
+    }
+
+    fn ld_f_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        // Only the low nibble selects the glyph, as on the original interpreter.
+        let digit = self.v[x as usize] & 0xF;
+        self.i = self.font_address as u32 + digit as u32 * FONT_GLYPH_SIZE as u32;
+        Ok(())
+    }
+
+    fn ld_i_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        for i in 0..=x as usize {
+            self.write_memory(self.i as usize + i, self.v[i])?;
         }
-        self.i += self.v[x as usize] as u16;
+        self.increment_i(x);
         Ok(())
     }
 
-    fn ld_f_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
+    fn ld_vx_i(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        for i in 0..=x as usize {
+            self.v[i] = self.read_memory(self.i as usize + i)?;
         }
-        let digit = self.v[x as usize];
-        if digit > 0xF {
-            return Err(format!("Invalid digit value: {}", digit));
+        self.increment_i(x);
+        Ok(())
+    }
+
+    fn ld_hf_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        let digit = self.v[x as usize] & 0xF;
+        self.i = BIG_FONT_ADDRESS as u32 + digit as u32 * BIG_FONT_GLYPH_SIZE as u32;
+        Ok(())
+    }
+
+    fn ld_r_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.rpl[..=x as usize].copy_from_slice(&self.v[..=x as usize]);
+        Ok(())
+    }
+
+    fn ld_vx_r(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.v[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
+        Ok(())
+    }
+
+    fn save_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
+        for (offset, register) in Self::register_range(x, y) {
+            self.write_memory(self.i as usize + offset, self.v[register])?;
         }
-        self.i = (digit as u16) * 5;
         Ok(())
     }
 
-    fn ld_i_vx(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
+    fn load_vx_vy(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.check_register(y)?;
+        for (offset, register) in Self::register_range(x, y) {
+            self.v[register] = self.read_memory(self.i as usize + offset)?;
         }
-        for i in 0..=x as usize {
-            self.memory[(self.i as usize) + i] = self.v[i];
+        Ok(())
+    }
+
+    // 5xy2/5xy3 walk the registers from x to y, in descending order when x > y.
+    fn register_range(x: u8, y: u8) -> impl Iterator<Item = (usize, usize)> {
+        let (x, y) = (x as usize, y as usize);
+        (0..=x.abs_diff(y)).map(move |offset| {
+            let register = if x <= y { x + offset } else { x - offset };
+            (offset, register)
+        })
+    }
+
+    fn ld_i_long(&mut self, nnnn: u16) -> Result<(), Chip8Error> {
+        self.i = nnnn as u32;
+        Ok(())
+    }
+
+    fn plane(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.planes = n & 0b11;
+        Ok(())
+    }
+
+    fn audio(&mut self) -> Result<(), Chip8Error> {
+        for offset in 0..AUDIO_PATTERN_SIZE {
+            self.audio_pattern[offset] = self.read_memory(self.i as usize + offset)?;
         }
+        self.pattern_loaded = true;
         Ok(())
     }
 
-    fn ld_vx_i(&mut self, x: u8) -> Result<(), String> {
-        if x as usize >= NUM_REGISTERS {
-            return Err(format!("Invalid register index: {}", x));
+    fn pitch_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_register(x)?;
+        self.pitch = self.v[x as usize];
+        Ok(())
+    }
+
+    // XO-CHIP skips over the whole four-byte F000 nnnn instruction.
+    fn skip_next_instruction(&mut self) {
+        let size = match self.fetch_opcode() {
+            Ok(next) if Opcode::is_long(next, self.platform) => 4,
+            _ => 2,
+        };
+        self.pc = self.pc.wrapping_add(size);
+    }
+
+    fn megaoff(&mut self) -> Result<(), Chip8Error> {
+        self.mega = false;
+        self.display = [0; DISPLAY_SIZE];
+        self.mega_frame = Vec::new();
+        Ok(())
+    }
+
+    fn megaon(&mut self) -> Result<(), Chip8Error> {
+        self.mega = true;
+        self.display = [0; DISPLAY_SIZE];
+        self.mega_frame = vec![BLACK; DISPLAY_SIZE];
+        Ok(())
+    }
+
+    fn ldhi_i(&mut self, address: u32) -> Result<(), Chip8Error> {
+        self.i = address;
+        Ok(())
+    }
+
+    // Loads nn ARGB colours from I into palette entries 1..=nn; entry 0 stays transparent.
+    fn ldpal(&mut self, nn: u8) -> Result<(), Chip8Error> {
+        for color in 0..nn as usize {
+            let address = self.i as usize + color * 4;
+            let mut argb = [0; 4];
+            for (offset, channel) in argb.iter_mut().enumerate() {
+                *channel = self.read_memory(address + offset)?;
+            }
+            self.palette[color + 1] = [argb[1], argb[2], argb[3], argb[0]];
         }
-        for i in 0..=x as usize {
-            self.v[i] = self.memory[(self.i as usize) + i];
+        Ok(())
+    }
+
+    fn sprw(&mut self, nn: u8) -> Result<(), Chip8Error> {
+        self.sprite_width = if nn == 0 { 256 } else { nn as usize };
+        Ok(())
+    }
+
+    fn sprh(&mut self, nn: u8) -> Result<(), Chip8Error> {
+        self.sprite_height = if nn == 0 { 256 } else { nn as usize };
+        Ok(())
+    }
+
+    fn set_alpha(&mut self, nn: u8) -> Result<(), Chip8Error> {
+        self.alpha = nn;
+        Ok(())
+    }
+
+    // The sound at I starts with a 6-byte header: 16-bit sample rate, 24-bit length and a reserved byte.
+    fn digisnd(&mut self, n: u8) -> Result<(), Chip8Error> {
+        let address = self.i as usize;
+        let mut header = [0; 6];
+        for (offset, byte) in header.iter_mut().enumerate() {
+            *byte = self.read_memory(address + offset)?;
+        }
+        let sample_rate = u16::from_be_bytes([header[0], header[1]]) as u32;
+        let length = u32::from_be_bytes([0, header[2], header[3], header[4]]) as usize;
+
+        let mut samples = Vec::with_capacity(length);
+        for offset in 0..length {
+            samples.push(self.read_memory(address + header.len() + offset)?);
+        }
+        self.digital_sound = Some(DigitalSound {
+            sample_rate,
+            samples: samples.into(),
+            looping: n == 0,
+        });
+        Ok(())
+    }
+
+    fn stopsnd(&mut self) -> Result<(), Chip8Error> {
+        self.digital_sound = None;
+        Ok(())
+    }
+
+    fn bmode(&mut self, n: u8) -> Result<(), Chip8Error> {
+        self.blend_mode = match n {
+            1 => BlendMode::Percent25,
+            2 => BlendMode::Percent50,
+            3 => BlendMode::Add,
+            4 => BlendMode::Multiply,
+            _ => BlendMode::Normal,
+        };
+        Ok(())
+    }
+
+    fn ccol(&mut self, nn: u8) -> Result<(), Chip8Error> {
+        self.collision_color = nn;
+        Ok(())
+    }
+
+    // MegaChip sprites are sprite_width x sprite_height palette indices, where index 0 is transparent.
+    fn draw_mega_sprite(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let width = MEGA_WIDTH as usize;
+        let height = MEGA_HEIGHT as usize;
+        let x_coord = self.v[x as usize] as usize;
+        let y_coord = self.v[y as usize] as usize;
+        self.v[0xF] = 0;
+
+        for row in 0..self.sprite_height {
+            let y = y_coord + row;
+            if y >= height {
+                break;
+            }
+            for column in 0..self.sprite_width {
+                let x = x_coord + column;
+                if x >= width {
+                    break;
+                }
+                let color = self.read_memory(self.i as usize + row * self.sprite_width + column)?;
+                if color == 0 {
+                    continue;
+                }
+                let index = y * width + x;
+
+                if self.display[index] == self.collision_color {
+                    self.v[0xF] = 1;
+                }
+                self.display[index] = color;
+                self.mega_frame[index] = self.blend(self.mega_frame[index], self.palette[color as usize]);
+            }
         }
         Ok(())
     }
+
+    fn blend(&self, destination: [u8; 4], source: [u8; 4]) -> [u8; 4] {
+        let opacity = source[3] as f32 / 255.0 * self.alpha as f32 / 255.0;
+        let mix = |weight: f32| {
+            let mut out = BLACK;
+            for channel in 0..3 {
+                let (d, s) = (destination[channel] as f32, source[channel] as f32);
+                out[channel] = (d + (s - d) * weight).round() as u8;
+            }
+            out
+        };
+
+        match self.blend_mode {
+            BlendMode::Normal => mix(opacity),
+            BlendMode::Percent25 => mix(0.25),
+            BlendMode::Percent50 => mix(0.5),
+            BlendMode::Add => {
+                let mut out = BLACK;
+                for channel in 0..3 {
+                    let added = destination[channel] as f32 + source[channel] as f32 * opacity;
+                    out[channel] = added.min(255.0) as u8;
+                }
+                out
+            }
+            BlendMode::Multiply => {
+                let mut out = BLACK;
+                for channel in 0..3 {
+                    out[channel] = (destination[channel] as u16 * source[channel] as u16 / 255) as u8;
+                }
+                out
+            }
+        }
+    }
+
+    fn increment_i(&mut self, x: u8) {
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(x as u32 + 1);
+        }
+    }
+}
+
+fn blend_mode_from_id(id: u8) -> Result<BlendMode, io::Error> {
+    match id {
+        0 => Ok(BlendMode::Normal),
+        1 => Ok(BlendMode::Percent25),
+        2 => Ok(BlendMode::Percent50),
+        3 => Ok(BlendMode::Add),
+        4 => Ok(BlendMode::Multiply),
+        _ => Err(savestate::invalid("unknown blend mode")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut cpu = CPU::with_seed(Platform::MegaChip, 1);
+        cpu.load_rom(&[0x00, 0x11, 0x60, 0x2A]).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let mut restored = CPU::with_seed(Platform::MegaChip, 2);
+        restored.load_rom(&[0x00, 0x11, 0x60, 0x2A]).unwrap();
+        restored.load_state(&cpu.save_state()).unwrap();
+        assert_eq!(restored.snapshot(), cpu.snapshot());
+    }
+
+    #[test]
+    fn restore_rejects_inconsistent_mega_state() {
+        let mut cpu = CPU::with_platform(Platform::MegaChip);
+        cpu.mega = true;
+        let state = cpu.snapshot();
+        let error = CPU::with_platform(Platform::MegaChip).restore(&state, SAVE_STATE_VERSION).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut cpu = CPU::new();
+        cpu.mega = true;
+        cpu.mega_frame = vec![BLACK; DISPLAY_SIZE];
+        let state = cpu.snapshot();
+        assert!(CPU::new().restore(&state, SAVE_STATE_VERSION).is_err());
+    }
+
+    #[test]
+    fn font_lookups_use_low_nibble() {
+        let mut cpu = CPU::with_platform(Platform::XoChip);
+        cpu.load_rom(&[0x60, 0x1A, 0xF0, 0x29, 0xF0, 0x30]).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.i(), FONT_ADDRESS as u32 + 0xA * FONT_GLYPH_SIZE as u32);
+        cpu.step().unwrap();
+        assert_eq!(cpu.i(), BIG_FONT_ADDRESS as u32 + 0xA * BIG_FONT_GLYPH_SIZE as u32);
+        assert_eq!(cpu.memory()[cpu.i() as usize], BIG_FONT_SET[0xA * BIG_FONT_GLYPH_SIZE]);
+    }
+
+    #[test]
+    fn dxy0_draws_nothing_on_chip8() {
+        let rom = [0xA2, 0x06, 0xD0, 0x10, 0x12, 0x04, 0xFF, 0xFF];
+        let mut cpu = CPU::with_platform(Platform::Chip8);
+        cpu.load_rom(&rom).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.display().iter().all(|&pixel| pixel == 0));
+        assert_eq!(cpu.v[0xF], 0);
+
+        let mut cpu = CPU::with_platform(Platform::SuperChip);
+        cpu.load_rom(&rom).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.display().iter().filter(|&&pixel| pixel != 0).count(), 16);
+    }
+
+    #[test]
+    fn load_state_rejects_other_rom() {
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x60, 0x01]).unwrap();
+        let mut other = CPU::new();
+        other.load_rom(&[0x60, 0x02]).unwrap();
+        assert_eq!(other.load_state(&cpu.save_state()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    // v0 := 5, i := 0x300, save v0, load v0, call 0x210, exit; 0x210: v0 += 1, return.
+    fn debug_cpu() -> CPU {
+        let mut cpu = CPU::with_platform(Platform::SuperChip);
+        let mut rom = vec![0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x65, 0x22, 0x10, 0x00, 0xFD];
+        rom.resize(0x10, 0);
+        rom.extend([0x70, 0x01, 0x00, 0xEE]);
+        cpu.load_rom(&rom).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn run_until_stops_at_breakpoints_and_resumes_past_them() {
+        let mut cpu = debug_cpu();
+        cpu.add_breakpoint(0x204);
+        assert_eq!(cpu.run_until(|_| false), StopReason::Breakpoint(0x204));
+        assert_eq!(cpu.pc(), 0x204);
+        assert_eq!(cpu.run_until(|_| false), StopReason::Halted);
+        assert_eq!(cpu.register_value(Register::V(0)), 6);
+    }
+
+    #[test]
+    fn run_until_reports_watchpoints() {
+        let mut cpu = debug_cpu();
+        cpu.add_watchpoint(0x300..=0x300, Access::Write);
+        assert_eq!(cpu.run_until(|_| false), StopReason::Watchpoint { address: 0x300, access: Access::Write });
+        assert_eq!(cpu.pc(), 0x206);
+
+        let mut cpu = debug_cpu();
+        cpu.add_watchpoint(0x2FF..=0x301, Access::Read);
+        assert_eq!(cpu.run_until(|_| false), StopReason::Watchpoint { address: 0x300, access: Access::Read });
+        assert_eq!(cpu.pc(), 0x208);
+        assert!(cpu.remove_watchpoint(0x2FF..=0x301, Access::Read));
+        assert_eq!(cpu.run_until(|_| false), StopReason::Halted);
+    }
+
+    #[test]
+    fn run_until_reports_register_changes_opcodes_and_conditions() {
+        let mut cpu = debug_cpu();
+        cpu.watch_register(Register::V(0));
+        let changed = StopReason::RegisterChanged { register: Register::V(0), old: 0, new: 5 };
+        assert_eq!(cpu.run_until(|_| false), changed);
+        cpu.unwatch_register(Register::V(0));
+
+        cpu.break_on_opcode("call");
+        assert_eq!(cpu.run_until(|_| false), StopReason::Opcode(Opcode::CALL(0x210)));
+        assert_eq!(cpu.pc(), 0x208);
+        cpu.clear_opcode_break("call");
+
+        assert_eq!(cpu.run_until(|cpu| cpu.sp > 0), StopReason::Condition);
+        assert_eq!(cpu.pc(), 0x210);
+    }
+
+    #[test]
+    fn run_until_stops_on_errors_and_key_waits() {
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x00, 0xEE]).unwrap();
+        assert!(matches!(cpu.run_until(|_| false), StopReason::Error(Chip8Error::StackUnderflow { pc: 0x200, .. })));
+
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0xF0, 0x0A]).unwrap();
+        assert_eq!(cpu.run_until(|_| false), StopReason::WaitingForKey);
+    }
+
+    #[test]
+    fn step_over_and_out_run_subroutines() {
+        let mut cpu = debug_cpu();
+        cpu.add_breakpoint(0x208);
+        assert_eq!(cpu.run_until(|_| false), StopReason::Breakpoint(0x208));
+        assert_eq!(cpu.step_over(1000), StopReason::StepComplete);
+        assert_eq!((cpu.pc(), cpu.register_value(Register::V(0))), (0x20A, 6));
+
+        let mut cpu = debug_cpu();
+        cpu.add_breakpoint(0x212);
+        assert_eq!(cpu.run_until(|_| false), StopReason::Breakpoint(0x212));
+        assert_eq!(cpu.step_out(1000), StopReason::StepComplete);
+        assert_eq!(cpu.pc(), 0x20A);
+        assert_eq!(cpu.step_over(1000), StopReason::StepComplete);
+        assert_eq!(cpu.run_until(|_| false), StopReason::Halted);
+    }
+
+    #[test]
+    fn stepping_into_an_endless_call_runs_out_of_budget() {
+        let mut cpu = CPU::new();
+        cpu.load_rom(&[0x22, 0x04, 0x12, 0x02, 0x12, 0x04]).unwrap();
+        assert_eq!(cpu.step_over(100), StopReason::BudgetExhausted);
+        assert_eq!(cpu.pc(), 0x204);
+        assert_eq!(cpu.step_out(100), StopReason::BudgetExhausted);
+        assert_eq!(cpu.run_until_for(10, |_| false), StopReason::BudgetExhausted);
+        assert_eq!(cpu.run_until_for(10, |cpu| cpu.pc() == 0x204), StopReason::Condition);
+    }
 }
@@ -0,0 +1,95 @@
+use crate::constants::DEFAULT_CYCLES_PER_FRAME;
+use crate::error::Chip8Error;
+use crate::opcode::Opcode;
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    SP,
+    DT,
+    ST,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<usize>,
+    pub access: Access,
+}
+
+// Why `run_until` and the stepping helpers handed control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(u16),
+    Watchpoint { address: usize, access: Access },
+    RegisterChanged { register: Register, old: u32, new: u32 },
+    Opcode(Opcode),
+    Condition,
+    // `run_until_for` or a stepping helper ran out of instructions.
+    BudgetExhausted,
+    StepComplete,
+    WaitingForKey,
+    Halted,
+    Error(Chip8Error),
+}
+
+#[derive(Clone)]
+pub(crate) struct DebugState {
+    pub(crate) breakpoints: BTreeSet<u16>,
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    pub(crate) registers: Vec<Register>,
+    pub(crate) opcode_breaks: BTreeSet<String>,
+    pub(crate) cycles_per_frame: u32,
+    pub(crate) frame_cycles: u32,
+    // First watchpoint hit by the instruction being executed.
+    pub(crate) hit: Cell<Option<StopReason>>,
+}
+
+impl Default for DebugState {
+    fn default() -> Self {
+        DebugState {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            registers: Vec::new(),
+            opcode_breaks: BTreeSet::new(),
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            frame_cycles: 0,
+            hit: Cell::new(None),
+        }
+    }
+}
+
+impl DebugState {
+    pub(crate) fn record_access(&self, address: usize, access: Access) {
+        if self.watchpoints.is_empty() || self.hit.get().is_some() {
+            return;
+        }
+        let watched = self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.access.covers(access) && watchpoint.range.contains(&address));
+        if watched {
+            self.hit.set(Some(StopReason::Watchpoint { address, access }));
+        }
+    }
+
+    pub(crate) fn breaks_on(&self, opcode: &Opcode) -> bool {
+        !self.opcode_breaks.is_empty() && self.opcode_breaks.contains(opcode.mnemonic())
+    }
+}
@@ -0,0 +1,320 @@
+use chip8_emulator::opcode::{self, Opcode};
+use chip8_emulator::{Chip8Error, StopReason, CPU};
+use egui::{Align, Color32, ClippedPrimitive, Context, DragValue, RichText, TexturesDelta, Ui};
+use egui_wgpu_backend::{BackendError, RenderPass, ScreenDescriptor};
+use egui_winit_platform::{Platform, PlatformDescriptor};
+use pixels::{wgpu, Pixels, PixelsContext};
+use std::time::Instant;
+use winit::event::Event;
+use winit::window::Window;
+
+const DISASSEMBLY_LINES: usize = 48;
+const LINES_BEFORE_PC: u16 = 16;
+const KEYPAD_LAYOUT: [[usize; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+const CURRENT_LINE_COLOR: Color32 = Color32::from_rgb(0xFF, 0xD7, 0x00);
+const ERROR_COLOR: Color32 = Color32::from_rgb(0xFF, 0x60, 0x60);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    // Running until a CALL returns to `return_pc` at the same stack depth.
+    StepOver { return_pc: u16, sp: u8 },
+}
+
+pub struct Debugger {
+    platform: Platform,
+    renderer: RenderPass,
+    screen: ScreenDescriptor,
+    textures: TexturesDelta,
+    paint_jobs: Vec<ClippedPrimitive>,
+    start: Instant,
+    visible: bool,
+    state: RunState,
+    error: Option<String>,
+    last_pc: u16,
+}
+
+impl Debugger {
+    pub fn new(window: &Window, pixels: &Pixels) -> Self {
+        let size = window.inner_size();
+        let scale_factor = window.scale_factor();
+        let platform = Platform::new(PlatformDescriptor {
+            physical_width: size.width,
+            physical_height: size.height,
+            scale_factor,
+            font_definitions: Default::default(),
+            style: Default::default(),
+        });
+        let renderer = RenderPass::new(pixels.device(), pixels.render_texture_format(), 1);
+
+        Debugger {
+            platform,
+            renderer,
+            screen: ScreenDescriptor {
+                physical_width: size.width,
+                physical_height: size.height,
+                scale_factor: scale_factor as f32,
+            },
+            textures: TexturesDelta::default(),
+            paint_jobs: Vec::new(),
+            start: Instant::now(),
+            visible: false,
+            state: RunState::Running,
+            error: None,
+            last_pc: 0,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event<()>) {
+        self.platform.handle_event(event);
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, scale_factor: f64) {
+        self.screen.physical_width = width;
+        self.screen.physical_height = height;
+        self.screen.scale_factor = scale_factor as f32;
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    // Runs one frame's worth of cycles unless execution is paused. A step over runs a frame
+    // at a time too, so a CALL that never returns doesn't freeze the window. A CPU error
+    // pauses execution and opens the overlay so the state that caused it can be inspected.
+    pub fn run_frame(&mut self, cpu: &mut CPU, cycles_per_frame: u32) -> Result<(), Chip8Error> {
+        let result = match self.state {
+            RunState::Running => cpu.run_frame(cycles_per_frame),
+            RunState::Paused => Ok(()),
+            RunState::StepOver { return_pc, sp } => {
+                let returned = |cpu: &CPU| cpu.pc() == return_pc && cpu.sp() <= sp;
+                let mut cycles = 0;
+                let reason = cpu.run_until(|cpu| {
+                    cycles += 1;
+                    cycles >= cycles_per_frame || returned(cpu)
+                });
+                match reason {
+                    StopReason::Condition if !returned(cpu) => Ok(()),
+                    StopReason::WaitingForKey => {
+                        cpu.tick_timers();
+                        Ok(())
+                    }
+                    StopReason::Error(e) => Err(e),
+                    _ => {
+                        self.state = RunState::Paused;
+                        Ok(())
+                    }
+                }
+            }
+        };
+        if let Err(e) = &result {
+            self.state = RunState::Paused;
+            self.visible = true;
+            self.error = Some(e.to_string());
+        }
+        result
+    }
+
+    pub fn prepare(&mut self, window: &Window, cpu: &mut CPU) {
+        if !self.visible {
+            return;
+        }
+
+        self.platform.update_time(self.start.elapsed().as_secs_f64());
+        self.platform.begin_frame();
+        let context = self.platform.context();
+        self.ui(&context, cpu);
+        let output = self.platform.end_frame(Some(window));
+
+        self.textures.append(output.textures_delta);
+        self.paint_jobs = context.tessellate(output.shapes);
+    }
+
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_target: &wgpu::TextureView,
+        context: &PixelsContext,
+    ) -> Result<(), BackendError> {
+        if !self.visible {
+            return Ok(());
+        }
+
+        self.renderer.add_textures(&context.device, &context.queue, &self.textures)?;
+        self.renderer.update_buffers(&context.device, &context.queue, &self.paint_jobs, &self.screen);
+        self.renderer.execute(encoder, render_target, &self.paint_jobs, &self.screen, None)?;
+        self.renderer.remove_textures(std::mem::take(&mut self.textures))
+    }
+
+    fn ui(&mut self, context: &Context, cpu: &mut CPU) {
+        egui::Window::new("Debugger").default_pos([8.0, 8.0]).show(context, |ui| {
+            self.controls(ui, cpu);
+            ui.separator();
+            egui::ScrollArea::vertical().id_source("state").max_height(220.0).show(ui, |ui| {
+                self.registers(ui, cpu);
+                ui.separator();
+                self.stack(ui, cpu);
+                ui.separator();
+                self.keypad(ui, cpu);
+            });
+        });
+
+        egui::Window::new("Disassembly").default_pos([360.0, 8.0]).show(context, |ui| {
+            self.disassembly(ui, cpu);
+        });
+    }
+
+    fn controls(&mut self, ui: &mut Ui, cpu: &mut CPU) {
+        ui.horizontal(|ui| {
+            let paused = self.is_paused();
+            if ui.button(if paused { "Run" } else { "Pause" }).clicked() {
+                self.state = if paused { RunState::Running } else { RunState::Paused };
+                self.error = None;
+            }
+            if ui.add_enabled(paused, egui::Button::new("Step")).clicked() {
+                self.step(cpu);
+            }
+            if ui.add_enabled(paused, egui::Button::new("Step over")).clicked() {
+                match cpu.next_opcode() {
+                    Some(Opcode::CALL(_)) => {
+                        self.state = RunState::StepOver { return_pc: cpu.pc().wrapping_add(2), sp: cpu.sp() };
+                    }
+                    _ => self.step(cpu),
+                }
+            }
+        });
+
+        match &self.error {
+            Some(error) => ui.label(RichText::new(error).color(ERROR_COLOR)),
+            None if cpu.is_waiting_for_key() => ui.label("Waiting for a key"),
+            None if self.is_paused() => ui.label("Paused"),
+            None if matches!(self.state, RunState::StepOver { .. }) => ui.label("Stepping over"),
+            None => ui.label("Running"),
+        };
+    }
+
+    fn step(&mut self, cpu: &mut CPU) {
+        if let Err(e) = cpu.step() {
+            self.error = Some(e.to_string());
+        }
+    }
+
+    fn registers(&mut self, ui: &mut Ui, cpu: &mut CPU) {
+        let editable = self.is_paused();
+
+        egui::Grid::new("registers").num_columns(8).show(ui, |ui| {
+            for x in 0..16 {
+                let mut value = cpu.registers()[x];
+                ui.label(format!("V{:X}", x));
+                if ui.add_enabled(editable, DragValue::new(&mut value).hexadecimal(2, false, true)).changed() {
+                    cpu.set_register(x, value);
+                }
+                if x % 4 == 3 {
+                    ui.end_row();
+                }
+            }
+        });
+
+        egui::Grid::new("special_registers").num_columns(6).show(ui, |ui| {
+            let mut i = cpu.i();
+            ui.label("I");
+            if ui.add_enabled(editable, DragValue::new(&mut i).hexadecimal(3, false, true)).changed() {
+                cpu.set_i(i);
+            }
+            let mut pc = cpu.pc();
+            ui.label("PC");
+            if ui.add_enabled(editable, DragValue::new(&mut pc).hexadecimal(3, false, true)).changed() {
+                cpu.set_pc(pc);
+            }
+            let mut sp = cpu.sp();
+            ui.label("SP");
+            if ui.add_enabled(editable, DragValue::new(&mut sp).clamp_range(0..=cpu.stack().len())).changed() {
+                cpu.set_sp(sp);
+            }
+            ui.end_row();
+
+            let mut delay = cpu.delay_timer();
+            ui.label("DT");
+            if ui.add_enabled(editable, DragValue::new(&mut delay)).changed() {
+                cpu.set_delay_timer(delay);
+            }
+            let mut sound = cpu.sound_timer();
+            ui.label("ST");
+            if ui.add_enabled(editable, DragValue::new(&mut sound)).changed() {
+                cpu.set_sound_timer(sound);
+            }
+            ui.end_row();
+        });
+    }
+
+    fn stack(&mut self, ui: &mut Ui, cpu: &mut CPU) {
+        let editable = self.is_paused();
+
+        ui.label("Stack");
+        egui::Grid::new("stack").num_columns(8).show(ui, |ui| {
+            for index in 0..cpu.stack().len() {
+                let mut address = cpu.stack()[index];
+                let label = RichText::new(format!("{:X}", index)).monospace();
+                // Entries at or above SP are stale and only shown for reference.
+                ui.label(if index < cpu.sp() as usize { label.strong() } else { label.weak() });
+                if ui.add_enabled(editable, DragValue::new(&mut address).hexadecimal(3, false, true)).changed() {
+                    cpu.set_stack_entry(index, address);
+                }
+                if index % 4 == 3 {
+                    ui.end_row();
+                }
+            }
+        });
+    }
+
+    fn keypad(&self, ui: &mut Ui, cpu: &CPU) {
+        ui.label("Keys");
+        egui::Grid::new("keypad").show(ui, |ui| {
+            for row in KEYPAD_LAYOUT {
+                for key in row {
+                    let text = RichText::new(format!(" {:X} ", key)).monospace();
+                    ui.label(if cpu.keys()[key] { text.background_color(CURRENT_LINE_COLOR).color(Color32::BLACK) } else { text });
+                }
+                ui.end_row();
+            }
+        });
+    }
+
+    fn disassembly(&mut self, ui: &mut Ui, cpu: &CPU) {
+        let pc = cpu.pc();
+        let follow = pc != self.last_pc;
+        self.last_pc = pc;
+
+        egui::ScrollArea::vertical().id_source("disassembly").max_height(260.0).show(ui, |ui| {
+            let mut address = pc.saturating_sub(LINES_BEFORE_PC * 2);
+            for _ in 0..DISASSEMBLY_LINES {
+                let Some(opcode) = decode_at(cpu, address) else {
+                    break;
+                };
+                let bytes = &cpu.memory()[address as usize..address as usize + opcode.size()];
+                let raw: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                let text = RichText::new(format!("{:03X}  {:<8}  {}", address, raw, opcode)).monospace();
+
+                if address == pc {
+                    let response = ui.label(text.color(CURRENT_LINE_COLOR));
+                    if follow {
+                        response.scroll_to_me(Some(Align::Center));
+                    }
+                } else {
+                    ui.label(text);
+                }
+                address = address.wrapping_add(opcode.size() as u16);
+            }
+        });
+    }
+}
+
+fn decode_at(cpu: &CPU, address: u16) -> Option<Opcode> {
+    let bytes = cpu.memory().get(address as usize..)?;
+    opcode::decode(bytes, cpu.platform())
+}
@@ -0,0 +1,180 @@
+use crate::opcode::{self, Opcode};
+use crate::platform::Platform;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const ROM_START: usize = 0x200;
+
+enum Line {
+    Code(Opcode),
+    Data(u8),
+}
+
+// Traces every path reachable from the entry point so that sprite data
+// embedded in the ROM is not decoded as instructions.
+fn trace(rom: &[u8], platform: Platform) -> (Vec<Option<Opcode>>, Vec<u16>) {
+    let mut code = vec![None; rom.len()];
+    let mut data_targets = Vec::new();
+    let mut pending = vec![ROM_START];
+
+    while let Some(address) = pending.pop() {
+        let Some(offset) = address.checked_sub(ROM_START) else {
+            continue;
+        };
+        if offset >= rom.len() || code[offset].is_some() {
+            continue;
+        }
+        let opcode = match opcode::decode(&rom[offset..], platform) {
+            Some(Opcode::INVALID(_)) | None => continue,
+            Some(opcode) => opcode,
+        };
+        code[offset] = Some(opcode);
+        let next = address + opcode.size();
+
+        match opcode {
+            Opcode::JP(nnn) => pending.push(nnn as usize),
+            Opcode::RET | Opcode::EXIT | Opcode::JP_V0_addr(_) => {}
+            Opcode::CALL(nnn) => {
+                pending.push(nnn as usize);
+                pending.push(next);
+            }
+            Opcode::SE_Vx_byte(..)
+            | Opcode::SNE_Vx_byte(..)
+            | Opcode::SE_Vx_Vy(..)
+            | Opcode::SNE_Vx_Vy(..)
+            | Opcode::SKP_Vx(_)
+            | Opcode::SKNP_Vx(_) => {
+                pending.push(next);
+                let skipped = next
+                    .checked_sub(ROM_START)
+                    .and_then(|offset| rom.get(offset..))
+                    .and_then(|bytes| opcode::decode(bytes, platform));
+                if let Some(skipped) = skipped {
+                    pending.push(next + skipped.size());
+                }
+            }
+            Opcode::LD_I_addr(nnn) | Opcode::LD_I_long(nnn) => {
+                data_targets.push(nnn);
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    (code, data_targets)
+}
+
+fn layout(rom: &[u8], code: &[Option<Opcode>]) -> Vec<(usize, Line)> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let address = ROM_START + offset;
+        match code[offset] {
+            Some(opcode) if offset + opcode.size() <= rom.len() => {
+                lines.push((address, Line::Code(opcode)));
+                offset += opcode.size();
+            }
+            _ => {
+                lines.push((address, Line::Data(rom[offset])));
+                offset += 1;
+            }
+        }
+    }
+    lines
+}
+
+fn sprite_row(byte: u8) -> String {
+    (0..8).rev().map(|bit| if byte >> bit & 1 == 1 { '#' } else { '.' }).collect()
+}
+
+// Disassembles a ROM loaded at 0x200. With `labels` the output is assembler
+// source: jump/call targets and sprite addresses get names, and the address
+// and raw bytes move into comments.
+pub fn disassemble(rom: &[u8], platform: Platform, labels: bool) -> String {
+    let (code, data_targets) = trace(rom, platform);
+    let lines = layout(rom, &code);
+
+    let mut names = BTreeMap::new();
+    if labels {
+        for (_, line) in &lines {
+            if let Line::Code(opcode) = line {
+                if let Some(target) = opcode.branch_target() {
+                    names.insert(target as usize, format!("label_{:03X}", target));
+                }
+            }
+        }
+        for target in data_targets {
+            names.entry(target as usize).or_insert_with(|| format!("data_{:03X}", target));
+        }
+        names.retain(|address, _| lines.iter().any(|(start, _)| start == address));
+    }
+    let name = |address: u16| names.get(&(address as usize)).cloned();
+
+    let mut output = String::new();
+    for (address, line) in &lines {
+        if let Some(label) = names.get(address) {
+            let _ = writeln!(output, "{}:", label);
+        }
+
+        let (raw, text, comment) = match line {
+            Line::Code(opcode) => {
+                let bytes = &rom[address - ROM_START..address - ROM_START + opcode.size()];
+                let raw: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                (raw, opcode.to_asm(name), None)
+            }
+            Line::Data(byte) => (
+                format!("{:02X}", byte),
+                format!("DB 0x{:02X}", byte),
+                Some(sprite_row(*byte)),
+            ),
+        };
+
+        let _ = if labels {
+            match comment {
+                Some(sprite) => writeln!(output, "    {:<24}; 0x{:03X}  {}  {}", text, address, raw, sprite),
+                None => writeln!(output, "    {:<24}; 0x{:03X}  {}", text, address, raw),
+            }
+        } else {
+            match comment {
+                Some(sprite) => writeln!(output, "0x{:03X}  {:<8}  {:<20}  ; {}", address, raw, text, sprite),
+                None => writeln!(output, "0x{:03X}  {:<8}  {}", address, raw, text),
+            }
+        };
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // I := sprite; draw it; loop forever; then a 5-byte sprite.
+    const ROM: [u8; 11] = [0xA2, 0x06, 0xD0, 0x15, 0x12, 0x04, 0xF0, 0x90, 0x90, 0x90, 0xF0];
+
+    #[test]
+    fn separates_code_from_data() {
+        let listing = disassemble(&ROM, Platform::Chip8, false);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], "0x200  A206      LD I, 0x206");
+        assert_eq!(lines[2], "0x204  1204      JP 0x204");
+        assert!(lines[3].starts_with("0x206  F0        DB 0xF0"));
+        assert!(lines[3].ends_with("; ####...."));
+    }
+
+    #[test]
+    fn labels_targets() {
+        let source = disassemble(&ROM, Platform::Chip8, true);
+        assert!(source.contains("    LD I, data_206"));
+        assert!(source.contains("label_204:\n    JP label_204"));
+        assert!(source.contains("data_206:\n    DB 0xF0"));
+    }
+
+    #[test]
+    fn long_instructions_are_not_split() {
+        let rom = [0xF0, 0x00, 0x02, 0x06, 0x12, 0x04, 0xFF];
+        let listing = disassemble(&rom, Platform::XoChip, false);
+        assert!(listing.starts_with("0x200  F0000206  LD I, LONG 0x0206\n0x204  1204"));
+    }
+}
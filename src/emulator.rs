@@ -1,7 +1,19 @@
 use std::{fs, io};
-use std::path::Path;
-use crate::constants::*;
-use crate::cpu::CPU;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::cli::EmulatorOptions;
+use crate::debugger::Debugger;
+use crate::gdb::GdbServer;
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::profiles;
+use crate::screenshot;
+use crate::sound::{self, AudioSink, NullSink};
+use crate::video::{self, VideoFormat, VideoRecorder};
+use chip8_emulator::audio::AudioState;
+use chip8_emulator::constants::*;
+use chip8_emulator::movie::Movie;
+use chip8_emulator::{Quirks, Rewind, CPU};
 use pixels::{Pixels, SurfaceTexture};
 use winit::event::{Event, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop};
@@ -9,34 +21,112 @@ use winit::platform::run_return::EventLoopExtRunReturn;
 use winit::window::{Window, WindowBuilder};
 use winit_input_helper::WinitInputHelper;
 
+const DEBUGGER_KEY: VirtualKeyCode = VirtualKeyCode::F10;
+const REWIND_KEY: VirtualKeyCode = VirtualKeyCode::Back;
+// F1-F9 save to slots 1-9, Shift+F1-F9 load them.
+const SAVE_SLOT_KEYS: [VirtualKeyCode; 9] = [
+    VirtualKeyCode::F1,
+    VirtualKeyCode::F2,
+    VirtualKeyCode::F3,
+    VirtualKeyCode::F4,
+    VirtualKeyCode::F5,
+    VirtualKeyCode::F6,
+    VirtualKeyCode::F7,
+    VirtualKeyCode::F8,
+    VirtualKeyCode::F9,
+];
+const SCREENSHOT_KEY: VirtualKeyCode = VirtualKeyCode::F12;
+const SCREENSHOTS_DIR: &str = "./screenshots";
+const VIDEO_KEY: VirtualKeyCode = VirtualKeyCode::F11;
+const VIDEOS_DIR: &str = "./videos";
+
 pub struct Emulator {
     window: Option<Window>,
     pixels: Option<Pixels>,
     input: WinitInputHelper,
     cpu: CPU,
+    cycles_per_frame: u32,
+    keymap: Keymap,
+    palette: Palette,
+    screenshot_scale: u32,
+    audio: Box<dyn AudioSink>,
+    quirks: Option<Quirks>,
+    gdb: Option<GdbServer>,
+    rewind: Rewind,
+    seed: u64,
+    record: Option<String>,
+    play: Option<String>,
+    video: Option<String>,
+    video_format: VideoFormat,
+    video_scale: u32,
 }
 
 impl Emulator {
-    pub fn new() -> Self {
+    pub fn new(options: &EmulatorOptions, gdb: Option<GdbServer>) -> Self {
         let input = WinitInputHelper::new();
+        let audio = sound::open_sink(options, false).unwrap_or_else(|e| {
+            eprintln!("Audio disabled: {}", e);
+            Box::new(NullSink)
+        });
+
+        // Always seed explicitly so a recorded movie can reproduce RND.
+        let seed = options.seed.unwrap_or_else(rand::random);
 
         Emulator {
             window: None,
             pixels: None,
             input,
-            cpu: CPU::new(),
+            cpu: CPU::with_seed(options.platform, seed),
+            cycles_per_frame: options.cycles_per_frame,
+            keymap: options.keymap.clone(),
+            palette: options.palette.clone(),
+            screenshot_scale: options.screenshot_scale,
+            audio,
+            quirks: options.quirks,
+            gdb,
+            rewind: Rewind::with_seconds(options.rewind_seconds, options.rewind_memory * 1024 * 1024),
+            seed,
+            record: options.record.clone(),
+            play: options.play.clone(),
+            video: options.video.clone(),
+            video_format: options.video_format,
+            video_scale: options.video_scale,
         }
     }
 
-    pub fn start(&mut self, mut event_loop: EventLoop<()>) -> Result<(), String> {
-        let game: String;
+    pub fn start(&mut self, mut event_loop: EventLoop<()>, game: &str) -> Result<(), String> {
+        let mut playback = match &self.play {
+            Some(path) => Some(Movie::load(Path::new(path)).map_err(|e| format!("Failed to load movie {}: {}", path, e))?),
+            None => None,
+        };
+        if let Some(movie) = &playback {
+            self.cpu = movie.cpu();
+            self.seed = movie.seed;
+            self.cycles_per_frame = movie.cycles_per_frame;
+        }
 
-        match Self::select_game() {
-            Ok(path) => game = path,
-            Err(e) => return Err(e.to_string()),
+        self.cpu.load_to_memory(game).map_err(|e| e.to_string())?;
+        match &playback {
+            Some(movie) => movie.check_rom(&self.cpu).map_err(|e| e.to_string())?,
+            None => {
+                let quirks = profiles::resolve_quirks(self.cpu.rom_hash(), self.quirks, true);
+                self.cpu.set_quirks(quirks.unwrap_or_else(|| self.cpu.platform().default_quirks()));
+            }
         }
-        
-        self.cpu.load_to_memory(&game).expect("Failed to load game");
+        let mut recording = self.record.as_ref().map(|_| {
+            Movie::new(self.cpu.rom_hash(), self.cpu.platform(), self.cpu.quirks(), self.seed, self.cycles_per_frame)
+        });
+
+        let mut video = match &self.video {
+            // The emulator prints status messages to stdout, which would corrupt the stream.
+            Some(path) if path == "-" => return Err("Streaming video to stdout needs run --headless".to_string()),
+            Some(path) => {
+                let recorder = VideoRecorder::create(path, self.cpu.platform(), self.video_scale)
+                    .map_err(|e| format!("Failed to create video {}: {}", path, e))?;
+                Some((recorder, path.clone()))
+            }
+            None => None,
+        };
 
         if self.window.is_none() {
             self.window = Some(WindowBuilder::new()
@@ -62,60 +152,279 @@ impl Emulator {
             ).expect("Failed to create pixels."));
         }
 
-        let mut input = self.input.clone();
-        let mut cpu = self.cpu.clone();
+        let input = &mut self.input;
+        let cpu = &mut self.cpu;
+        let cycles_per_frame = self.cycles_per_frame;
+        let gdb = &mut self.gdb;
+        let rewind = &mut self.rewind;
+        let mut rewinding = false;
+        let recorder = &mut recording;
+        let record_path = self.record.clone();
+        let clip = &mut video;
+        let video_format = self.video_format;
+        let video_scale = self.video_scale;
+        let mut frame = 0;
+        let keymap = &self.keymap;
+        let palette = &self.palette;
+        let screenshot_scale = self.screenshot_scale;
+        let audio = &mut self.audio;
         let window = self.window.take().unwrap();
         let mut pixels = self.pixels.take().unwrap();
+        let mut debugger = Debugger::new(&window, &pixels);
+        let frame_duration = Duration::from_secs(1) / TIMER_FREQUENCY;
+        let mut next_frame = Instant::now();
+        let mut waiting_for_key = false;
+        let game = game.to_string();
+        cpu.set_debug_cycles_per_frame(cycles_per_frame);
 
         event_loop.run_return(move |event, _, control_flow| {
+            debugger.handle_event(&event);
+
             if input.update(&event) {
                 if input.key_pressed(VirtualKeyCode::Escape) || input.close_requested() {
                     *control_flow = ControlFlow::Exit;
                     return;
                 }
+
+                if input.key_pressed(DEBUGGER_KEY) {
+                    debugger.toggle();
+                    window.request_redraw();
+                }
+
+                for (index, &code) in SAVE_SLOT_KEYS.iter().enumerate() {
+                    if input.key_pressed(code) {
+                        let slot = index + 1;
+                        let path = Self::slot_path(&game, slot);
+                        if input.held_shift() {
+                            match Self::load_slot(cpu, &path) {
+                                Ok(()) => {
+                                    println!("Loaded state from slot {}", slot);
+                                    // Rewind history and movies belong to the machine that was replaced,
+                                    // and a movie can't replay a loaded state.
+                                    rewind.clear();
+                                    if let (Some(movie), Some(path)) = (recorder.take(), &record_path) {
+                                        println!("Stopped recording at frame {}", frame);
+                                        if let Err(e) = Self::finish_movie(&movie, path) {
+                                            eprintln!("{}", e);
+                                        }
+                                    }
+                                    if playback.take().is_some() {
+                                        println!("Stopped the movie at frame {}", frame);
+                                    }
+                                }
+                                Err(e) => eprintln!("Failed to load slot {}: {}", slot, e),
+                            }
+                            window.request_redraw();
+                        } else {
+                            match Self::save_slot(cpu, &path) {
+                                Ok(()) => println!("Saved state to slot {}", slot),
+                                Err(e) => eprintln!("Failed to save slot {}: {}", slot, e),
+                            }
+                        }
+                    }
+                }
+
+                if input.key_pressed(SCREENSHOT_KEY) {
+                    let path = Path::new(SCREENSHOTS_DIR).join(screenshot::file_name(&game, frame as u64));
+                    match screenshot::save(&path, cpu, palette, screenshot_scale) {
+                        Ok(()) => println!("Saved screenshot to {}", path.display()),
+                        Err(e) => eprintln!("Failed to save screenshot: {}", e),
+                    }
+                }
+
+                if input.key_pressed(VIDEO_KEY) {
+                    match clip.take() {
+                        Some((recorder, path)) => Self::finish_video(recorder, &path),
+                        None => {
+                            let name = video::file_name(&game, frame as u64, video_format);
+                            let path = Path::new(VIDEOS_DIR).join(name).to_string_lossy().into_owned();
+                            match VideoRecorder::create(&path, cpu.platform(), video_scale) {
+                                Ok(recorder) => {
+                                    println!("Recording video to {}", path);
+                                    *clip = Some((recorder, path));
+                                }
+                                Err(e) => eprintln!("Failed to start video: {}", e),
+                            }
+                        }
+                    }
+                }
+
+                if let Some(size) = input.window_resized() {
+                    if let Err(e) = pixels.resize_surface(size.width, size.height) {
+                        eprintln!("pixels.resize_surface() failed: {}", e);
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                    debugger.resize(size.width, size.height, window.scale_factor());
+                }
+
+                rewinding = input.key_held(REWIND_KEY);
+
+                // Pressing any keypad key during playback takes over with live input.
+                if playback.is_some() && keymap.iter().any(|(_, code)| input.key_pressed(code)) {
+                    println!("Took over from the movie at frame {}", frame);
+                    playback = None;
+                }
             }
 
             match event {
                 Event::RedrawRequested(_) => {
-                    self.run_cycle(&mut cpu, &mut pixels);
+                    Self::draw(cpu, &mut pixels, palette);
+                    debugger.prepare(&window, cpu);
 
-                    if let Err(e) = pixels.render() {
+                    let result = pixels.render_with(|encoder, render_target, context| {
+                        context.scaling_renderer.render(encoder, render_target);
+                        debugger.render(encoder, render_target, context)?;
+                        Ok(())
+                    });
+                    if let Err(e) = result {
                         eprintln!("pixels.render() failed: {}", e);
                         *control_flow = ControlFlow::Exit;
-                        return;
                     }
                 }
                 Event::MainEventsCleared => {
-                    window.request_redraw();
+                    let now = Instant::now();
+                    if now >= next_frame {
+                        if let Some(gdb) = gdb.as_mut() {
+                            gdb.poll(cpu);
+                        }
+                        let paused = debugger.is_paused() || gdb.as_ref().is_some_and(|gdb| gdb.is_stopped());
+                        if rewinding && !paused {
+                            // Step back one recorded frame per frame, so history replays in reverse
+                            // at normal speed.
+                            frame -= rewind.rewind(cpu, 1);
+                            if let Some(movie) = recorder.as_mut() {
+                                movie.truncate(frame);
+                            }
+                            audio.frame(AudioState::default());
+                        } else {
+                            // Keys are sampled once per frame so recordings see exactly what the CPU saw.
+                            let playing = !paused && playback.as_ref().is_some_and(|movie| movie.play(frame, cpu));
+                            if !playing && !paused && playback.take().is_some() {
+                                println!("Movie finished at frame {}, switching to live input", frame);
+                            }
+                            if playback.is_none() {
+                                for (key, code) in keymap.iter() {
+                                    cpu.set_key(key, input.key_held(code));
+                                }
+                            }
+
+                            audio.frame(if paused { AudioState::default() } else { cpu.audio_state() });
+                            if !paused {
+                                if let Some(movie) = recorder.as_mut() {
+                                    movie.record(cpu.keys());
+                                }
+                                frame += 1;
+                            }
+                            Self::run_frame(cpu, &mut debugger, gdb.as_mut(), cycles_per_frame);
+                            if !paused {
+                                rewind.record(cpu);
+                            }
+                        }
+                        // Rewound frames are recorded too, so the video shows what was on screen.
+                        if let Some((recorder, path)) = clip.as_mut().filter(|_| !paused) {
+                            if let Err(e) = recorder.frame(cpu, palette) {
+                                eprintln!("Failed to write video {}: {}", path, e);
+                                *clip = None;
+                            }
+                        }
+                        window.request_redraw();
+
+                        if cpu.is_waiting_for_key() != waiting_for_key {
+                            waiting_for_key = cpu.is_waiting_for_key();
+                            window.set_title(if waiting_for_key {
+                                "Chip8 Emulator - Press a key"
+                            } else {
+                                "Chip8 Emulator"
+                            });
+                        }
+
+                        next_frame += frame_duration;
+                        if next_frame < now {
+                            next_frame = now + frame_duration;
+                        }
+                    }
+                    *control_flow = ControlFlow::WaitUntil(next_frame);
                 }
                 _ => (),
             }
         });
+
+        if let Some((recorder, path)) = video {
+            Self::finish_video(recorder, &path);
+        }
+        if let (Some(path), Some(movie)) = (&self.record, &recording) {
+            Self::finish_movie(movie, path)?;
+        }
+        self.audio.finish().map_err(|e| e.to_string())
+    }
+
+    // An attached GDB client takes over execution control from the debugger overlay.
+    fn run_frame(cpu: &mut CPU, debugger: &mut Debugger, gdb: Option<&mut GdbServer>, cycles_per_frame: u32) {
+        let result = match gdb {
+            Some(gdb) if gdb.is_attached() => gdb.run_frame(cpu, cycles_per_frame),
+            _ => debugger.run_frame(cpu, cycles_per_frame),
+        };
+        if let Err(e) = result {
+            match cpu.source_location(e.pc()) {
+                Some(location) => eprintln!("CPU execution error: {} in {}", e, location),
+                None => eprintln!("CPU execution error: {}", e),
+            }
+        }
+    }
+
+    fn slot_path(game: &str, slot: usize) -> PathBuf {
+        let name = Path::new(game).file_stem().map_or_else(|| game.into(), |stem| stem.to_string_lossy());
+        Path::new(SAVES_DIR).join(format!("{}.{}.state", name, slot))
+    }
+
+    fn save_slot(cpu: &CPU, path: &Path) -> Result<(), io::Error> {
+        fs::create_dir_all(SAVES_DIR)?;
+        fs::write(path, cpu.save_state())
+    }
+
+    fn load_slot(cpu: &mut CPU, path: &Path) -> Result<(), io::Error> {
+        let state = fs::read(path)?;
+        cpu.load_state(&state)
+    }
+
+    fn finish_movie(movie: &Movie, path: &str) -> Result<(), String> {
+        movie.save(Path::new(path)).map_err(|e| format!("Failed to write movie {}: {}", path, e))?;
+        println!("Recorded {} frames to {}", movie.len(), path);
         Ok(())
     }
 
-    fn run_cycle(&mut self, cpu: &mut CPU, pixels: &mut Pixels) {
-        if let Err(e) = self.cpu.execute_cycle() {
-            eprintln!("CPU execution error: {}", e);
+    fn finish_video(recorder: VideoRecorder, path: &str) {
+        let frames = recorder.frames();
+        match recorder.finish() {
+            Ok(()) => println!("Saved {} frames of video to {}", frames, path),
+            Err(e) => eprintln!("Failed to write video {}: {}", path, e),
         }
-        Self::draw(cpu, pixels);
     }
 
-    fn draw(cpu: &CPU, pixels: &mut Pixels) {
+    fn draw(cpu: &CPU, pixels: &mut Pixels, palette: &Palette) {
+        let width = cpu.display_width() as u32;
+        let height = cpu.display_height() as u32;
+        if pixels.texture().width() != width || pixels.texture().height() != height {
+            if let Err(e) = pixels.resize_buffer(width, height) {
+                eprintln!("pixels.resize_buffer() failed: {}", e);
+                return;
+            }
+        }
+
         let frame = pixels.frame_mut();
-        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            let x = i % CHIP8_WIDTH as usize;
-            let y = i / CHIP8_WIDTH as usize;
-            let color = if cpu.display[y * CHIP8_WIDTH as usize + x] {
-                [0xFF, 0xFF, 0xFF, 0xFF]
-            } else {
-                [0x00, 0x00, 0x00, 0xFF]
-            };
-            pixel.copy_from_slice(&color);
+        if let Some(mega_frame) = cpu.mega_frame() {
+            for (pixel, color) in frame.chunks_exact_mut(4).zip(mega_frame) {
+                pixel.copy_from_slice(color);
+            }
+            return;
+        }
+        for (pixel, &planes) in frame.chunks_exact_mut(4).zip(cpu.display()) {
+            pixel.copy_from_slice(&palette.color(planes));
         }
     }
 
-    fn select_game() -> Result<String, io::Error> {
+    pub fn select_game() -> Result<String, io::Error> {
         let games_dir = Path::new("./src/games");
         let entries = fs::read_dir(games_dir)?;
         let mut games = Vec::new();
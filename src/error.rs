@@ -1,8 +1,52 @@
-#[derive(Debug)]
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Chip8Error {
-    InvalidOpcode(u16),
-    StackOverflow,
-    StackUnderflow,
-    InvalidMemoryAccess(usize),
-    InvalidRegister(u8),
+    InvalidOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16, opcode: u16 },
+    StackUnderflow { pc: u16, opcode: u16 },
+    InvalidMemoryAccess { pc: u16, opcode: u16, address: usize },
+    InvalidRegister { pc: u16, opcode: u16, register: u8 },
 }
+
+impl Chip8Error {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            Chip8Error::InvalidOpcode { pc, .. }
+            | Chip8Error::StackOverflow { pc, .. }
+            | Chip8Error::StackUnderflow { pc, .. }
+            | Chip8Error::InvalidMemoryAccess { pc, .. }
+            | Chip8Error::InvalidRegister { pc, .. } => pc,
+        }
+    }
+
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            Chip8Error::InvalidOpcode { opcode, .. }
+            | Chip8Error::StackOverflow { opcode, .. }
+            | Chip8Error::StackUnderflow { opcode, .. }
+            | Chip8Error::InvalidMemoryAccess { opcode, .. }
+            | Chip8Error::InvalidRegister { opcode, .. } => opcode,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Chip8Error::InvalidOpcode { .. } => write!(f, "Invalid opcode")?,
+            Chip8Error::StackOverflow { .. } => write!(f, "Stack overflow")?,
+            Chip8Error::StackUnderflow { .. } => write!(f, "Stack underflow")?,
+            Chip8Error::InvalidMemoryAccess { address, .. } => {
+                write!(f, "Invalid memory access at 0x{:04X}", address)?
+            }
+            Chip8Error::InvalidRegister { register, .. } => {
+                write!(f, "Invalid register index: {}", register)?
+            }
+        }
+        write!(f, " (opcode 0x{:04X} at 0x{:04X})", self.opcode(), self.pc())
+    }
+}
+
+impl Error for Chip8Error {}
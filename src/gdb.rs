@@ -0,0 +1,597 @@
+use chip8_emulator::{Access, Chip8Error, StopReason, CPU};
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// GDB numbers the registers in this order: V0-VF, I, PC, SP.
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REGISTER_COUNT: usize = 19;
+const PACKET_SIZE: usize = 0x4000;
+const INTERRUPT: u8 = 0x03;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    no_ack: bool,
+}
+
+// A GDB remote serial protocol stub. The emulator polls it once per frame; while a
+// client is attached it decides whether the CPU runs.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<Client>,
+    stopped: bool,
+    // Set by `c` so the instruction at PC runs even if it has a breakpoint.
+    resumed: bool,
+    last_stop: String,
+}
+
+impl GdbServer {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbServer {
+            listener,
+            client: None,
+            stopped: false,
+            resumed: false,
+            last_stop: stop_signal(SIGTRAP),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<String> {
+        Ok(self.listener.local_addr()?.to_string())
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.is_attached() && self.stopped
+    }
+
+    // Accepts a new client and handles every packet it has sent since the last poll.
+    pub fn poll(&mut self, cpu: &mut CPU) {
+        if self.client.is_none() {
+            self.accept();
+        }
+        if let Err(e) = self.receive(cpu) {
+            eprintln!("GDB connection closed: {}", e);
+            self.disconnect(cpu);
+        }
+    }
+
+    // Runs a frame for an attached client, reporting the stop reason if something fires.
+    pub fn run_frame(&mut self, cpu: &mut CPU, cycles_per_frame: u32) -> Result<(), Chip8Error> {
+        if self.stopped {
+            return Ok(());
+        }
+
+        let at_breakpoint = !self.resumed && cpu.breakpoints().any(|address| address == cpu.pc());
+        self.resumed = false;
+        let reason = if at_breakpoint {
+            StopReason::Breakpoint(cpu.pc())
+        } else {
+            let mut cycles = 0;
+            cpu.run_until(|_| {
+                cycles += 1;
+                cycles >= cycles_per_frame
+            })
+        };
+
+        match reason {
+            StopReason::Condition => Ok(()),
+            StopReason::WaitingForKey => {
+                cpu.tick_timers();
+                Ok(())
+            }
+            reason => {
+                self.stop(reason);
+                match reason {
+                    StopReason::Error(e) => Err(e),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        match self.listener.accept() {
+            Ok((stream, peer)) => {
+                println!("GDB attached from {}", peer);
+                let _ = stream.set_nodelay(true);
+                self.client = Some(Client { stream, buffer: Vec::new(), no_ack: false });
+                // GDB expects the target to be stopped when it attaches.
+                self.stopped = true;
+                self.last_stop = stop_signal(SIGTRAP);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => eprintln!("Failed to accept GDB connection: {}", e),
+        }
+    }
+
+    fn disconnect(&mut self, cpu: &mut CPU) {
+        self.client = None;
+        self.stopped = false;
+        cpu.clear_debug_points();
+    }
+
+    fn receive(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        let Some(client) = &mut self.client else {
+            return Ok(());
+        };
+
+        client.stream.set_nonblocking(true)?;
+        let mut chunk = [0; 4096];
+        let result = loop {
+            match client.stream.read(&mut chunk) {
+                Ok(0) => break Err(io::Error::new(ErrorKind::UnexpectedEof, "client disconnected")),
+                Ok(count) => client.buffer.extend_from_slice(&chunk[..count]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        client.stream.set_nonblocking(false)?;
+        result?;
+
+        while let Some(packet) = self.next_packet()? {
+            if self.client.is_none() {
+                break;
+            }
+            self.handle_packet(&packet, cpu)?;
+        }
+        Ok(())
+    }
+
+    // Pops the next complete packet off the input buffer, acknowledging it. An interrupt
+    // byte is returned as a packet of its own.
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        let Some(client) = &mut self.client else {
+            return Ok(None);
+        };
+
+        loop {
+            let Some(&first) = client.buffer.first() else {
+                return Ok(None);
+            };
+            if first == INTERRUPT {
+                client.buffer.remove(0);
+                return Ok(Some(String::from(INTERRUPT as char)));
+            }
+            if first != b'$' {
+                // Acks and line noise between packets.
+                client.buffer.remove(0);
+                continue;
+            }
+
+            let Some(end) = client.buffer.iter().position(|&byte| byte == b'#') else {
+                return Ok(None);
+            };
+            if client.buffer.len() < end + 3 {
+                return Ok(None);
+            }
+
+            let packet: Vec<u8> = client.buffer.drain(..end + 3).collect();
+            let payload = unescape(&packet[1..end]);
+            let checksum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let valid = checksum == Some(packet[1..end].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+
+            if !client.no_ack {
+                client.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &str, cpu: &mut CPU) -> io::Result<()> {
+        if packet.as_bytes() == [INTERRUPT] {
+            if !self.stopped {
+                self.stopped = true;
+                self.last_stop = stop_signal(SIGINT);
+                return self.send(&self.last_stop.clone());
+            }
+            return Ok(());
+        }
+
+        let (Some(command), Some(args)) = (packet.get(..1), packet.get(1..)) else {
+            return self.send("");
+        };
+        let reply = match command {
+            "?" => self.last_stop.clone(),
+            "g" => (0..REGISTER_COUNT).map(|n| hex(&read_register(cpu, n))).collect(),
+            "G" => write_registers(cpu, args),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTER_COUNT => hex(&read_register(cpu, n)),
+                _ => String::from("E01"),
+            },
+            "P" => write_register(cpu, args),
+            "m" => read_memory(cpu, args),
+            "M" => write_memory(cpu, args),
+            "Z" | "z" => self.set_debug_point(cpu, command == "Z", args),
+            "s" => {
+                if let Ok(address) = u16::from_str_radix(args, 16) {
+                    cpu.set_pc(address);
+                }
+                let reason = match cpu.step_instruction() {
+                    StopReason::WaitingForKey | StopReason::Condition => StopReason::StepComplete,
+                    reason => reason,
+                };
+                self.last_stop = stop_reply(reason);
+                self.last_stop.clone()
+            }
+            "c" => {
+                if let Ok(address) = u16::from_str_radix(args, 16) {
+                    cpu.set_pc(address);
+                }
+                self.stopped = false;
+                self.resumed = true;
+                // The stop reply is sent once the CPU stops.
+                return Ok(());
+            }
+            "D" => {
+                self.send("OK")?;
+                println!("GDB detached");
+                self.disconnect(cpu);
+                return Ok(());
+            }
+            "k" => {
+                println!("GDB detached");
+                self.disconnect(cpu);
+                return Ok(());
+            }
+            "H" => String::from("OK"),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+
+        self.send(&reply)?;
+        if packet == "QStartNoAckMode" {
+            if let Some(client) = &mut self.client {
+                client.no_ack = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE);
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let description = target_description();
+            let Some((offset, length)) = parse_pair(range) else {
+                return String::from("E01");
+            };
+            let start = offset.min(description.len());
+            let end = start.saturating_add(length).min(description.len());
+            let prefix = if end == description.len() { 'l' } else { 'm' };
+            return format!("{}{}", prefix, &description[start..end]);
+        }
+        match packet {
+            "QStartNoAckMode" => String::from("OK"),
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    fn set_debug_point(&mut self, cpu: &mut CPU, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(address), Some(length)) = (fields.next(), fields.next(), fields.next()) else {
+            return String::from("E01");
+        };
+        let (Ok(address), Ok(length)) = (usize::from_str_radix(address, 16), usize::from_str_radix(length, 16)) else {
+            return String::from("E01");
+        };
+
+        let access = match kind {
+            "0" | "1" => {
+                if insert {
+                    cpu.add_breakpoint(address as u16);
+                } else {
+                    cpu.remove_breakpoint(address as u16);
+                }
+                return String::from("OK");
+            }
+            "2" => Access::Write,
+            "3" => Access::Read,
+            "4" => Access::ReadWrite,
+            _ => return String::new(),
+        };
+
+        let Some(end) = address.checked_add(length.max(1) - 1) else {
+            return String::from("E01");
+        };
+        let range = address..=end;
+        if insert {
+            cpu.add_watchpoint(range, access);
+        } else {
+            cpu.remove_watchpoint(range, access);
+        }
+        String::from("OK")
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        self.stopped = true;
+        self.last_stop = stop_reply(reason);
+        if let Err(e) = self.send(&self.last_stop.clone()) {
+            eprintln!("Failed to notify GDB: {}", e);
+        }
+    }
+
+    fn send(&mut self, payload: &str) -> io::Result<()> {
+        let Some(client) = &mut self.client else {
+            return Ok(());
+        };
+        let checksum = payload.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        client.stream.write_all(format!("${}#{:02x}", payload, checksum).as_bytes())
+    }
+}
+
+fn stop_signal(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint { address, access } => {
+            let kind = match access {
+                Access::Read => "rwatch",
+                Access::Write => "watch",
+                Access::ReadWrite => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, address)
+        }
+        StopReason::Halted => String::from("W00"),
+        StopReason::Error(Chip8Error::InvalidOpcode { .. }) => stop_signal(SIGILL),
+        StopReason::Error(_) => stop_signal(SIGSEGV),
+        _ => stop_signal(SIGTRAP),
+    }
+}
+
+fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\"><feature name=\"org.chip8.core\">",
+    );
+    for x in 0..16 {
+        let _ = write!(xml, "<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", x);
+    }
+    xml.push_str("<reg name=\"i\" bitsize=\"32\" type=\"data_ptr\"/>");
+    xml.push_str("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>");
+    xml.push_str("<reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>");
+    xml.push_str("</feature></target>");
+    xml
+}
+
+// Register contents in target byte order (little-endian).
+fn read_register(cpu: &CPU, n: usize) -> Vec<u8> {
+    match n {
+        REG_I => cpu.i().to_le_bytes().to_vec(),
+        REG_PC => cpu.pc().to_le_bytes().to_vec(),
+        REG_SP => vec![cpu.sp()],
+        x => vec![cpu.registers()[x]],
+    }
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        REG_I => 4,
+        REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn set_register(cpu: &mut CPU, n: usize, bytes: &[u8]) {
+    let value = bytes.iter().rev().fold(0u32, |value, &byte| value << 8 | byte as u32);
+    match n {
+        REG_I => cpu.set_i(value),
+        REG_PC => cpu.set_pc(value as u16),
+        REG_SP => cpu.set_sp(value as u8),
+        x => cpu.set_register(x, value as u8),
+    }
+}
+
+fn write_registers(cpu: &mut CPU, args: &str) -> String {
+    let Some(bytes) = unhex(args) else {
+        return String::from("E01");
+    };
+    let mut offset = 0;
+    for n in 0..REGISTER_COUNT {
+        let size = register_size(n);
+        let Some(value) = bytes.get(offset..offset + size) else {
+            break;
+        };
+        set_register(cpu, n, value);
+        offset += size;
+    }
+    String::from("OK")
+}
+
+fn write_register(cpu: &mut CPU, args: &str) -> String {
+    let Some((n, value)) = args.split_once('=') else {
+        return String::from("E01");
+    };
+    match (usize::from_str_radix(n, 16), unhex(value)) {
+        (Ok(n), Some(bytes)) if n < REGISTER_COUNT && bytes.len() == register_size(n) => {
+            set_register(cpu, n, &bytes);
+            String::from("OK")
+        }
+        _ => String::from("E01"),
+    }
+}
+
+fn read_memory(cpu: &CPU, args: &str) -> String {
+    let Some((address, length)) = parse_pair(args) else {
+        return String::from("E01");
+    };
+    let length = length.min(PACKET_SIZE / 2);
+    match cpu.memory().get(address..address.saturating_add(length).min(cpu.memory().len())) {
+        Some(bytes) if !bytes.is_empty() || length == 0 => hex(bytes),
+        _ => String::from("E01"),
+    }
+}
+
+fn write_memory(cpu: &mut CPU, args: &str) -> String {
+    let Some((range, data)) = args.split_once(':') else {
+        return String::from("E01");
+    };
+    let (Some((address, length)), Some(bytes)) = (parse_pair(range), unhex(data)) else {
+        return String::from("E01");
+    };
+    if bytes.len() != length || address.checked_add(length).is_none_or(|end| end > cpu.memory().len()) {
+        return String::from("E01");
+    }
+    for (offset, byte) in bytes.into_iter().enumerate() {
+        cpu.set_memory(address + offset, byte);
+    }
+    String::from("OK")
+}
+
+fn parse_pair(args: &str) -> Option<(usize, usize)> {
+    let (first, second) = args.split_once(',')?;
+    Some((usize::from_str_radix(first, 16).ok()?, usize::from_str_radix(second, 16).ok()?))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+// Binary packet payloads escape `#`, `$`, `}` and `*` as `}` followed by the byte XOR 0x20.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut escaped = false;
+    for &byte in data {
+        if escaped {
+            bytes.push(byte ^ 0x20);
+            escaped = false;
+        } else if byte == b'}' {
+            escaped = true;
+        } else {
+            bytes.push(byte);
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    // V0 := 5; loop: V0 += 1; jump loop
+    const ROM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    struct Session {
+        server: GdbServer,
+        cpu: CPU,
+        client: TcpStream,
+        buffer: Vec<u8>,
+    }
+
+    impl Session {
+        fn attach() -> Session {
+            let server = GdbServer::bind("127.0.0.1:0").unwrap();
+            let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+            client.set_nonblocking(true).unwrap();
+            let mut cpu = CPU::new();
+            cpu.load_rom(&ROM).unwrap();
+            let mut session = Session { server, cpu, client, buffer: Vec::new() };
+            while !session.server.is_attached() {
+                session.server.poll(&mut session.cpu);
+            }
+            session
+        }
+
+        // Sends a packet and drives the emulator until the reply arrives.
+        fn request(&mut self, payload: &str) -> String {
+            let checksum = payload.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            self.client.write_all(format!("${}#{:02x}", payload, checksum).as_bytes()).unwrap();
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while Instant::now() < deadline {
+                self.server.poll(&mut self.cpu);
+                if !self.server.is_stopped() {
+                    let _ = self.server.run_frame(&mut self.cpu, 10);
+                }
+
+                let mut chunk = [0; 4096];
+                match self.client.read(&mut chunk) {
+                    Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                    Err(e) => panic!("{}", e),
+                }
+                let start = self.buffer.iter().position(|&byte| byte == b'$');
+                let end = self.buffer.iter().position(|&byte| byte == b'#');
+                if let (Some(start), Some(end)) = (start, end) {
+                    if self.buffer.len() >= end + 3 {
+                        let reply = String::from_utf8_lossy(&self.buffer[start + 1..end]).into_owned();
+                        self.buffer.drain(..end + 3);
+                        return reply;
+                    }
+                }
+            }
+            panic!("no reply to {}", payload);
+        }
+    }
+
+    #[test]
+    fn registers_and_stepping() {
+        let mut session = Session::attach();
+        assert_eq!(session.request("?"), "S05");
+        assert_eq!(session.request("s"), "S05");
+
+        let registers = session.request("g");
+        assert_eq!(registers.len(), (16 + 4 + 2 + 1) * 2);
+        assert_eq!(&registers[..2], "05");
+        assert_eq!(&registers[40..44], "0202");
+    }
+
+    #[test]
+    fn memory_round_trip() {
+        let mut session = Session::attach();
+        assert_eq!(session.request("m200,2"), "6005");
+        assert_eq!(session.request("M300,2:abcd"), "OK");
+        assert_eq!(session.request("m300,2"), "abcd");
+        assert_eq!(session.request("M1000,1:00"), "E01");
+    }
+
+    #[test]
+    fn oversized_addresses_are_rejected() {
+        let mut session = Session::attach();
+        assert_eq!(session.request("Mffffffffffffffff,2:abcd"), "E01");
+        assert_eq!(session.request("Z2,ffffffffffffffff,2"), "E01");
+        assert_eq!(session.request("qXfer:features:read:target.xml:0,ffffffffffffffff").chars().next(), Some('l'));
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoint() {
+        let mut session = Session::attach();
+        assert_eq!(session.request("Z0,204,2"), "OK");
+        assert_eq!(session.request("c"), "S05");
+        assert_eq!(session.request("p11"), "0402");
+        assert_eq!(session.request("p0"), "06");
+
+        assert_eq!(session.request("z0,204,2"), "OK");
+        assert_eq!(session.request("s"), "S05");
+        assert_eq!(session.request("p11"), "0202");
+    }
+}
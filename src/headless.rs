@@ -0,0 +1,165 @@
+use crate::cli::RunCommand;
+use crate::profiles;
+use crate::screenshot;
+use crate::sound;
+use crate::video::VideoRecorder;
+use chip8_emulator::movie::Movie;
+use chip8_emulator::CPU;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+const EXIT_CPU_ERROR: u8 = 1;
+const EXIT_IO_ERROR: u8 = 2;
+
+pub fn run(run_command: &RunCommand) -> ExitCode {
+    let options = &run_command.options;
+    let playback = match options.play.as_deref().map(|path| Movie::load(Path::new(path))).transpose() {
+        Ok(playback) => playback,
+        Err(e) => {
+            eprintln!("Failed to load movie: {}", e);
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    };
+    let seed = options.seed.unwrap_or_else(rand::random);
+    let mut cpu = match &playback {
+        Some(movie) => movie.cpu(),
+        None => CPU::with_seed(options.platform, seed),
+    };
+
+    if let Err(e) = cpu.load_to_memory(&run_command.rom) {
+        eprintln!("Failed to load {}: {}", run_command.rom, e);
+        return ExitCode::from(EXIT_IO_ERROR);
+    }
+    match &playback {
+        Some(movie) => {
+            if let Err(e) = movie.check_rom(&cpu) {
+                eprintln!("Cannot play {}: {}", run_command.rom, e);
+                return ExitCode::from(EXIT_IO_ERROR);
+            }
+        }
+        None => {
+            let quirks = profiles::resolve_quirks(cpu.rom_hash(), options.quirks, false);
+            cpu.set_quirks(quirks.unwrap_or_else(|| cpu.platform().default_quirks()));
+        }
+    }
+
+    let mut audio = match sound::open_sink(&run_command.options, true) {
+        Ok(audio) => audio,
+        Err(e) => {
+            eprintln!("Failed to open audio output: {}", e);
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    // A movie replays every recorded frame at the speed it was recorded with.
+    let (cycles_per_frame, cycles) = match &playback {
+        Some(movie) => {
+            let cycles_per_frame = movie.cycles_per_frame.max(1) as u64;
+            (cycles_per_frame, movie.len() as u64 * cycles_per_frame)
+        }
+        None => (options.cycles_per_frame.max(1) as u64, run_command.cycles),
+    };
+    let mut recording = options.record.as_ref().map(|_| {
+        let seed = playback.as_ref().map_or(seed, |movie| movie.seed);
+        Movie::new(cpu.rom_hash(), cpu.platform(), cpu.quirks(), seed, cycles_per_frame as u32)
+    });
+
+    let mut video = match options.video.as_deref().map(|path| VideoRecorder::create(path, cpu.platform(), options.video_scale)).transpose() {
+        Ok(video) => video,
+        Err(e) => {
+            eprintln!("Failed to create video {}: {}", options.video.as_deref().unwrap_or_default(), e);
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    };
+
+    let mut frames = 0;
+    for cycle in 0..cycles {
+        if cpu.is_halted() {
+            break;
+        }
+        if cycle % cycles_per_frame == 0 {
+            let frame = (cycle / cycles_per_frame) as usize;
+            if let Some(movie) = &playback {
+                movie.play(frame, &mut cpu);
+            }
+            if let Some(movie) = recording.as_mut() {
+                movie.record(cpu.keys());
+            }
+        }
+        if let Err(e) = cpu.step() {
+            match cpu.source_location(e.pc()) {
+                Some(location) => eprintln!("CPU execution error at cycle {}: {} in {}", cycle, e, location),
+                None => eprintln!("CPU execution error at cycle {}: {}", cycle, e),
+            }
+            status = ExitCode::from(EXIT_CPU_ERROR);
+            break;
+        }
+        if (cycle + 1) % cycles_per_frame == 0 {
+            audio.frame(cpu.audio_state());
+            cpu.tick_timers();
+            frames += 1;
+            if let Some(recorder) = video.as_mut() {
+                if let Err(e) = recorder.frame(&cpu, &options.palette) {
+                    eprintln!("Failed to write video: {}", e);
+                    return ExitCode::from(EXIT_IO_ERROR);
+                }
+            }
+        }
+    }
+
+    if let Some(recorder) = video {
+        if let Err(e) = recorder.finish() {
+            eprintln!("Failed to write video: {}", e);
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    }
+
+    if let (Some(path), Some(movie)) = (&options.record, &recording) {
+        if let Err(e) = movie.save(Path::new(path)) {
+            eprintln!("Failed to write {}: {}", path, e);
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    }
+
+    if let Err(e) = audio.finish() {
+        eprintln!("Failed to write audio output: {}", e);
+        return ExitCode::from(EXIT_IO_ERROR);
+    }
+
+    if let Some(target) = &run_command.screenshot {
+        let path = screenshot::resolve_path(target, &run_command.rom, frames);
+        if let Err(e) = screenshot::save(&path, &cpu, &options.palette, options.screenshot_scale) {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    }
+
+    let dump = display_to_ascii(cpu.display(), cpu.display_width());
+    match &run_command.output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, dump) {
+                eprintln!("Failed to write {}: {}", path, e);
+                return ExitCode::from(EXIT_IO_ERROR);
+            }
+        }
+        // A video streamed to stdout must not have the dump appended to it.
+        None if options.video.as_deref() == Some("-") => (),
+        None => print!("{}", dump),
+    }
+
+    status
+}
+
+const PIXEL_CHARS: [char; 4] = ['.', '#', '+', '@'];
+
+fn display_to_ascii(display: &[u8], width: usize) -> String {
+    let mut dump = String::with_capacity(display.len() + display.len() / width);
+
+    for row in display.chunks_exact(width) {
+        dump.extend(row.iter().map(|&pixel| *PIXEL_CHARS.get(pixel as usize).unwrap_or(&'#')));
+        dump.push('\n');
+    }
+    dump
+}
@@ -0,0 +1,70 @@
+use winit::event::VirtualKeyCode;
+
+pub const DEFAULT_KEYMAP: &str = "1234QWERASDFZXCV";
+
+// Hex keypad keys in the order they appear on the COSMAC VIP keypad, row by row.
+#[rustfmt::skip]
+const KEYPAD_LAYOUT: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+#[derive(Clone)]
+pub struct Keymap {
+    keys: [VirtualKeyCode; 16],
+}
+
+impl Keymap {
+    pub fn parse(layout: &str) -> Result<Keymap, String> {
+        let codes = layout
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| Self::key_code(c).ok_or_else(|| format!("Unsupported key '{}'", c)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if codes.len() != KEYPAD_LAYOUT.len() {
+            return Err(format!(
+                "Expected {} keys, got {}",
+                KEYPAD_LAYOUT.len(),
+                codes.len()
+            ));
+        }
+
+        let mut keys = [VirtualKeyCode::Key0; 16];
+        for (&key, code) in KEYPAD_LAYOUT.iter().zip(codes) {
+            keys[key] = code;
+        }
+        Ok(Keymap { keys })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, VirtualKeyCode)> + '_ {
+        self.keys.iter().copied().enumerate()
+    }
+
+    #[rustfmt::skip]
+    fn key_code(c: char) -> Option<VirtualKeyCode> {
+        use VirtualKeyCode::*;
+
+        let code = match c.to_ascii_uppercase() {
+            '0' => Key0, '1' => Key1, '2' => Key2, '3' => Key3, '4' => Key4,
+            '5' => Key5, '6' => Key6, '7' => Key7, '8' => Key8, '9' => Key9,
+            'A' => A, 'B' => B, 'C' => C, 'D' => D, 'E' => E, 'F' => F, 'G' => G,
+            'H' => H, 'I' => I, 'J' => J, 'K' => K, 'L' => L, 'M' => M, 'N' => N,
+            'O' => O, 'P' => P, 'Q' => Q, 'R' => R, 'S' => S, 'T' => T, 'U' => U,
+            'V' => V, 'W' => W, 'X' => X, 'Y' => Y, 'Z' => Z,
+            ',' => Comma, '.' => Period, ';' => Semicolon, '/' => Slash,
+            '\'' => Apostrophe, '[' => LBracket, ']' => RBracket, '-' => Minus,
+            '=' => Equals,
+            _ => return None,
+        };
+        Some(code)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::parse(DEFAULT_KEYMAP).expect("default keymap is valid")
+    }
+}
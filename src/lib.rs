@@ -0,0 +1,25 @@
+pub mod assembler;
+pub mod audio;
+pub mod constants;
+pub mod cpu;
+pub mod debug;
+pub mod disassembler;
+pub mod error;
+pub mod font;
+pub mod movie;
+pub mod octo;
+pub mod opcode;
+pub mod platform;
+pub mod quirks;
+pub mod rom;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+
+pub use cpu::{MemoryAccess, CPU};
+pub use debug::{Access, Register, StopReason};
+pub use error::Chip8Error;
+pub use opcode::Opcode;
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use rewind::Rewind;
@@ -1,18 +1,29 @@
 mod cli;
 mod commands;
-mod constants;
-mod cpu;
+mod debugger;
 mod emulator;
-mod opcode;
-mod error;
+mod gdb;
+mod headless;
+mod keymap;
+mod palette;
+mod profiles;
+mod screenshot;
+mod sound;
+mod video;
 
 use crate::cli::{Cli, Commands};
 use clap::Parser;
+use std::process::ExitCode;
 
-fn main() {
+fn main() -> ExitCode {
     let cli = Cli::parse();
     match cli.command {
         Commands::Add(add_command) => commands::handle_add_command(&add_command),
-        Commands::Start => commands::handle_start_command(),
+        Commands::Start(start_command) => commands::handle_start_command(&start_command),
+        Commands::Run(run_command) => return commands::handle_run_command(&run_command),
+        Commands::Disasm(disasm_command) => return commands::handle_disasm_command(&disasm_command),
+        Commands::Asm(asm_command) => return commands::handle_asm_command(&asm_command),
+        Commands::Compile(compile_command) => return commands::handle_compile_command(&compile_command),
     }
+    ExitCode::SUCCESS
 }
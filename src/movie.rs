@@ -0,0 +1,195 @@
+use crate::cpu::CPU;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::savestate::{invalid, StateReader, StateWriter};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"C8MV";
+pub const MOVIE_VERSION: u16 = 1;
+const NUM_KEYS: usize = 16;
+
+// A recorded session: everything needed to recreate the machine plus the keypad state of
+// every frame, one bit per key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub cycles_per_frame: u32,
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: u64, platform: Platform, quirks: Quirks, seed: u64, cycles_per_frame: u32) -> Self {
+        Movie { rom_hash, platform, quirks, seed, cycles_per_frame, frames: Vec::new() }
+    }
+
+    // A CPU configured the way the movie was recorded, ready for the ROM to be loaded.
+    pub fn cpu(&self) -> CPU {
+        let mut cpu = CPU::with_seed(self.platform, self.seed);
+        cpu.set_quirks(self.quirks);
+        cpu
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn record(&mut self, keys: &[bool]) {
+        let mask = keys.iter().take(NUM_KEYS).enumerate().fold(0, |mask, (key, &pressed)| mask | (pressed as u16) << key);
+        self.frames.push(mask);
+    }
+
+    // Drops every frame from `frames` on, e.g. after rewinding during a recording.
+    pub fn truncate(&mut self, frames: usize) {
+        self.frames.truncate(frames);
+    }
+
+    // Sets the keypad to the state recorded for `frame`. Returns false once the movie has ended.
+    pub fn play(&self, frame: usize, cpu: &mut CPU) -> bool {
+        let Some(&mask) = self.frames.get(frame) else {
+            return false;
+        };
+        for key in 0..NUM_KEYS {
+            cpu.set_key(key, mask >> key & 1 == 1);
+        }
+        true
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut movie = StateWriter::default();
+        movie.bytes(MAGIC);
+        movie.u16(MOVIE_VERSION);
+        movie.u64(self.rom_hash);
+        movie.u8(self.platform.id());
+        movie.u8(self.quirks.bits());
+        movie.u64(self.seed);
+        movie.u32(self.cycles_per_frame);
+        movie.u32(self.frames.len() as u32);
+
+        // Keypad states are run-length encoded, since keys are usually held for many frames.
+        for run in self.frames.chunk_by(|a, b| a == b) {
+            movie.u32(run.len() as u32);
+            movie.u16(run[0]);
+        }
+        movie.data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, io::Error> {
+        let mut movie = StateReader::new(data);
+        if movie.bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(invalid("not a movie file"));
+        }
+        let version = movie.u16()?;
+        if version == 0 || version > MOVIE_VERSION {
+            return Err(invalid(&format!("unsupported movie version {}", version)));
+        }
+
+        let rom_hash = movie.u64()?;
+        let platform = Platform::from_id(movie.u8()?).ok_or_else(|| invalid("unknown platform"))?;
+        let quirks = Quirks::from_bits(movie.u8()?);
+        let seed = movie.u64()?;
+        let cycles_per_frame = movie.u32()?;
+        let length = movie.u32()? as usize;
+
+        let mut frames = Vec::new();
+        while frames.len() < length {
+            let run = movie.u32()? as usize;
+            let mask = movie.u16()?;
+            if run == 0 || frames.len() + run > length {
+                return Err(invalid("corrupt movie frames"));
+            }
+            frames.resize(frames.len() + run, mask);
+        }
+
+        Ok(Movie { rom_hash, platform, quirks, seed, cycles_per_frame, frames })
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, io::Error> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        fs::write(path, self.to_bytes())
+    }
+
+    // Movies only replay correctly against the exact ROM they were recorded with.
+    pub fn check_rom(&self, cpu: &CPU) -> Result<(), io::Error> {
+        if cpu.rom_hash() != self.rom_hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Movie was recorded with a different ROM"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 6] = [0xC0, 0xFF, 0xF1, 0x0A, 0x12, 0x00];
+
+    fn keys(mask: u16) -> [bool; NUM_KEYS] {
+        std::array::from_fn(|key| mask >> key & 1 == 1)
+    }
+
+    #[test]
+    fn run_length_round_trip() {
+        let mut movie = Movie::new(0x1234, Platform::XoChip, Quirks::XO_CHIP, 42, 11);
+        for mask in [0, 0, 0, 0x8001, 0x8001, 0, 0xFFFF] {
+            movie.record(&keys(mask));
+        }
+        let bytes = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(&bytes).unwrap(), movie);
+
+        // Repeating the last frame only extends its run; a different frame starts a new one.
+        let mut longer = movie.clone();
+        longer.record(&keys(0xFFFF));
+        longer.record(&keys(0xFFFF));
+        assert_eq!(longer.to_bytes().len(), bytes.len());
+        assert_eq!(Movie::from_bytes(&longer.to_bytes()).unwrap(), longer);
+        longer.record(&keys(0));
+        assert_eq!(longer.to_bytes().len(), bytes.len() + 6);
+        assert!(Movie::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn play_sets_recorded_keys() {
+        let mut movie = Movie::new(0, Platform::Chip8, Quirks::default(), 0, 10);
+        movie.record(&keys(0b1010));
+        let mut cpu = movie.cpu();
+        assert!(movie.play(0, &mut cpu));
+        assert_eq!(cpu.keys(), &keys(0b1010));
+        assert!(!movie.play(1, &mut cpu));
+    }
+
+    #[test]
+    fn check_rom_rejects_other_rom() {
+        let mut cpu = CPU::new();
+        cpu.load_rom(&ROM).unwrap();
+        let movie = Movie::new(cpu.rom_hash(), Platform::Chip8, Quirks::default(), 7, 10);
+        assert!(movie.check_rom(&cpu).is_ok());
+
+        let mut other = CPU::new();
+        other.load_rom(&ROM[..4]).unwrap();
+        assert_eq!(movie.check_rom(&other).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn same_seed_replays_rnd() {
+        let movie = Movie::new(0, Platform::Chip8, Quirks::default(), 99, 10);
+        let run = || {
+            let mut cpu = movie.cpu();
+            cpu.load_rom(&ROM).unwrap();
+            cpu.step().unwrap();
+            cpu.registers()[0]
+        };
+        assert_eq!(run(), run());
+    }
+}
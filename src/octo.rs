@@ -0,0 +1,982 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::f64::consts::{E, PI};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+const ROM_START: usize = 0x200;
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+const VF: u8 = 0xF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl Error for OctoError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+// Maps the address of every compiled instruction back to the Octo line it came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    entries: BTreeMap<u16, SourceLocation>,
+}
+
+impl SourceMap {
+    pub fn lookup(&self, address: u16) -> Option<&SourceLocation> {
+        self.entries.get(&address)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &SourceLocation)> {
+        self.entries.iter().map(|(&address, location)| (address, location))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub source_map: SourceMap,
+}
+
+pub fn compile(source: &str, file: &str) -> Result<Program, OctoError> {
+    Compiler::new(tokenize(source, file)).run()
+}
+
+pub fn compile_file(path: &Path) -> Result<Program, OctoError> {
+    let file = path.display().to_string();
+    let source = fs::read_to_string(path)
+        .map_err(|e| OctoError { file: file.clone(), line: 0, column: 0, message: e.to_string() })?;
+    compile(&source, &file)
+}
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    file: Rc<str>,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> OctoError {
+        OctoError { file: self.file.to_string(), line: self.line, column: self.column, message: message.into() }
+    }
+}
+
+// Octo tokens are separated by whitespace; `#` starts a comment.
+fn tokenize(source: &str, file: &str) -> Vec<Token> {
+    let file: Rc<str> = Rc::from(file);
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut start = None;
+        for (column, c) in line.char_indices().chain([(line.len(), ' ')]) {
+            if c == '#' && start.is_none() {
+                break;
+            }
+            match (c.is_whitespace(), start) {
+                (true, Some(begin)) => {
+                    tokens.push(Token {
+                        text: line[begin..column].to_string(),
+                        file: file.clone(),
+                        line: index + 1,
+                        column: begin + 1,
+                    });
+                    start = None;
+                }
+                (false, None) => start = Some(column),
+                _ => (),
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value as f64 } else { value as f64 })
+}
+
+#[derive(Clone, Copy)]
+enum Fixup {
+    Address,
+    Long,
+    HighNibble,
+    LowByte,
+}
+
+enum Operand {
+    Register(u8),
+    Number(f64),
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Key,
+    NotKey,
+}
+
+struct Condition {
+    register: u8,
+    comparison: Comparison,
+    operand: Option<Operand>,
+}
+
+enum Control {
+    If(usize),
+    Else(usize),
+    Loop { start: usize, exits: Vec<usize> },
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Compiler {
+    tokens: Vec<Token>,
+    position: usize,
+    pending: Vec<Token>,
+    expansions: usize,
+    here: usize,
+    rom: Vec<u8>,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Fixup, Token)>,
+    control: Vec<(Control, Token)>,
+    source_map: SourceMap,
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>) -> Self {
+        Compiler {
+            tokens,
+            position: 0,
+            pending: Vec::new(),
+            expansions: 0,
+            here: ROM_START,
+            rom: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            control: Vec::new(),
+            source_map: SourceMap::default(),
+        }
+    }
+
+    fn run(mut self) -> Result<Program, OctoError> {
+        // Execution starts at `main`, wherever it ends up.
+        let file = self.tokens.first().map_or_else(|| Rc::from(""), |token| token.file.clone());
+        let entry = Token { text: "main".to_string(), file, line: 1, column: 1 };
+        self.fixups.push((self.here, Fixup::Address, entry.clone()));
+        self.emit(0x1000, &entry);
+
+        while let Some(token) = self.next() {
+            self.statement(token)?;
+        }
+
+        if let Some((_, token)) = self.control.pop() {
+            return Err(token.error(format!("`{}` is never closed", token.text)));
+        }
+        if !self.labels.contains_key("main") {
+            return Err(entry.error("missing `main` label"));
+        }
+        for (address, kind, token) in std::mem::take(&mut self.fixups) {
+            let Some(&target) = self.labels.get(&token.text) else {
+                return Err(token.error(format!("undefined label `{}`", token.text)));
+            };
+            self.patch(address, kind, target, &token)?;
+        }
+
+        Ok(Program { rom: self.rom, source_map: self.source_map })
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        if let Some(token) = self.pending.pop() {
+            return Some(token);
+        }
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&str> {
+        match self.pending.last() {
+            Some(token) => Some(&token.text),
+            None => self.tokens.get(self.position).map(|token| token.text.as_str()),
+        }
+    }
+
+    fn expect(&mut self, after: &Token) -> Result<Token, OctoError> {
+        self.next().ok_or_else(|| after.error(format!("unexpected end of file after `{}`", after.text)))
+    }
+
+    fn expect_text(&mut self, after: &Token, text: &str) -> Result<Token, OctoError> {
+        let token = self.expect(after)?;
+        if token.text != text {
+            return Err(token.error(format!("expected `{}`, found `{}`", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn write(&mut self, address: usize, byte: u8) {
+        let offset = address - ROM_START;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        self.write(self.here, byte);
+        self.here += 1;
+    }
+
+    fn emit(&mut self, word: u16, token: &Token) {
+        if let Ok(address) = u16::try_from(self.here) {
+            let location = SourceLocation { file: token.file.to_string(), line: token.line };
+            self.source_map.entries.insert(address, location);
+        }
+        self.emit_byte((word >> 8) as u8);
+        self.emit_byte(word as u8);
+    }
+
+    fn patch(&mut self, address: usize, kind: Fixup, target: usize, token: &Token) -> Result<(), OctoError> {
+        let offset = address - ROM_START;
+        match kind {
+            Fixup::Address => {
+                if target > 0xFFF {
+                    return Err(token.error(format!("address 0x{:X} of `{}` does not fit in 12 bits", target, token.text)));
+                }
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (target >> 8) as u8;
+                self.rom[offset + 1] = target as u8;
+            }
+            Fixup::Long => {
+                self.rom[offset] = (target >> 8) as u8;
+                self.rom[offset + 1] = target as u8;
+            }
+            Fixup::HighNibble => self.rom[offset] = (self.rom[offset] & 0xF0) | ((target >> 8) & 0xF) as u8,
+            Fixup::LowByte => self.rom[offset] = target as u8,
+        }
+        Ok(())
+    }
+
+    fn define(&self, token: &Token) -> Result<String, OctoError> {
+        let name = &token.text;
+        if self.labels.contains_key(name) || self.constants.contains_key(name) || self.aliases.contains_key(name) {
+            return Err(token.error(format!("`{}` is already defined", name)));
+        }
+        Ok(name.clone())
+    }
+
+    fn register(&self, token: &Token) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(&token.text) {
+            return Some(register);
+        }
+        let mut chars = token.text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|x| x as u8),
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self, after: &Token) -> Result<u8, OctoError> {
+        let token = self.expect(after)?;
+        self.register(&token).ok_or_else(|| token.error(format!("expected a register, found `{}`", token.text)))
+    }
+
+    // A number, constant, already defined label or `{ ... }` expression.
+    fn number(&mut self, token: &Token) -> Result<Option<f64>, OctoError> {
+        if token.text == "{" {
+            return self.calc_block(token).map(Some);
+        }
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(Some(value));
+        }
+        if let Some(&value) = self.constants.get(&token.text) {
+            return Ok(Some(value));
+        }
+        Ok(self.labels.get(&token.text).map(|&address| address as f64))
+    }
+
+    fn value(&mut self, after: &Token, min: i64, max: i64) -> Result<i64, OctoError> {
+        let token = self.expect(after)?;
+        let value = self.number(&token)?.ok_or_else(|| token.error(format!("undefined name `{}`", token.text)))?;
+        Self::check_range(&token, value, min, max)
+    }
+
+    fn check_range(token: &Token, value: f64, min: i64, max: i64) -> Result<i64, OctoError> {
+        let value = value.floor() as i64;
+        if value < min || value > max {
+            return Err(token.error(format!("value {} is out of range ({}..={})", value, min, max)));
+        }
+        Ok(value)
+    }
+
+    fn byte(&mut self, after: &Token) -> Result<u8, OctoError> {
+        self.value(after, -128, 255).map(|value| value as u8)
+    }
+
+    fn nibble(&mut self, after: &Token) -> Result<u8, OctoError> {
+        self.value(after, 0, 15).map(|value| value as u8)
+    }
+
+    // Emits `opcode | address`, deferring labels that are not defined yet.
+    fn emit_address(&mut self, opcode: u16, after: &Token, kind: Fixup) -> Result<(), OctoError> {
+        let token = self.expect(after)?;
+        self.emit_address_of(opcode, after, token, kind)
+    }
+
+    fn emit_address_of(&mut self, opcode: u16, after: &Token, token: Token, kind: Fixup) -> Result<(), OctoError> {
+        let max = if let Fixup::Long = kind { 0xFFFF } else { 0xFFF };
+        let address = match self.number(&token)? {
+            Some(value) => Self::check_range(&token, value, 0, max)? as u16,
+            None => {
+                let address = if let Fixup::Long = kind { self.here + 2 } else { self.here };
+                self.fixups.push((address, kind, token));
+                0
+            }
+        };
+        match kind {
+            Fixup::Long => {
+                self.emit(opcode, after);
+                self.emit_byte((address >> 8) as u8);
+                self.emit_byte(address as u8);
+            }
+            _ => self.emit(opcode | address, after),
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), OctoError> {
+        if let Some(x) = self.register(&token) {
+            return self.assignment(x, &token);
+        }
+        let x = |x: u8| (x as u16) << 8;
+        let xy = |x: u8, y: u8| ((x as u16) << 8) | ((y as u16) << 4);
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.expect(&token)?;
+                let name = self.define(&name)?;
+                self.labels.insert(name, self.here);
+            }
+            ":next" => {
+                let name = self.expect(&token)?;
+                let name = self.define(&name)?;
+                self.labels.insert(name, self.here + 1);
+            }
+            ":alias" => {
+                let name = self.expect(&token)?;
+                let name = self.define(&name)?;
+                let value = self.expect(&token)?;
+                match self.register(&value) {
+                    Some(register) => {
+                        self.aliases.insert(name, register);
+                    }
+                    None if value.text == "{" => {
+                        let value = self.calc_block(&value)?;
+                        self.constants.insert(name, value);
+                    }
+                    None => return Err(value.error(format!("expected a register, found `{}`", value.text))),
+                }
+            }
+            ":const" => {
+                let name = self.expect(&token)?;
+                let name = self.define(&name)?;
+                let value = self.expect(&token)?;
+                let number = self.number(&value)?;
+                let number = number.ok_or_else(|| value.error(format!("undefined name `{}`", value.text)))?;
+                self.constants.insert(name, number);
+            }
+            ":calc" => {
+                let name = self.expect(&token)?;
+                let name = self.define(&name)?;
+                let open = self.expect_text(&token, "{")?;
+                let value = self.calc_block(&open)?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = self.expect(&token)?;
+                match self.number(&value)? {
+                    Some(number) => {
+                        let byte = Self::check_range(&value, number, -128, 255)? as u8;
+                        self.emit_byte(byte);
+                    }
+                    None => {
+                        self.fixups.push((self.here, Fixup::LowByte, value));
+                        self.emit_byte(0);
+                    }
+                }
+            }
+            ":pointer" => {
+                let value = self.expect(&token)?;
+                let address = match self.number(&value)? {
+                    Some(number) => Self::check_range(&value, number, 0, 0xFFFF)? as u16,
+                    None => {
+                        self.fixups.push((self.here, Fixup::Long, value));
+                        0
+                    }
+                };
+                self.emit_byte((address >> 8) as u8);
+                self.emit_byte(address as u8);
+            }
+            ":org" => self.here = self.value(&token, ROM_START as i64, 0xFFFFFF)? as usize,
+            ":call" => self.emit_address(0x2000, &token, Fixup::Address)?,
+            ":unpack" => {
+                let high = self.nibble(&token)?;
+                let label = self.expect(&token)?;
+                let address = match self.number(&label)? {
+                    Some(number) => Self::check_range(&label, number, 0, 0xFFF)? as u16,
+                    None => {
+                        self.fixups.push((self.here + 1, Fixup::HighNibble, label.clone()));
+                        self.fixups.push((self.here + 3, Fixup::LowByte, label));
+                        0
+                    }
+                };
+                self.emit(0x6000 | ((high as u16) << 4) | (address >> 8), &token);
+                self.emit(0x6100 | (address & 0xFF), &token);
+            }
+            ":macro" => self.define_macro(&token)?,
+            ":breakpoint" => {
+                self.expect(&token)?;
+            }
+            ":monitor" => {
+                self.expect(&token)?;
+                self.expect(&token)?;
+            }
+            ";" | "return" => self.emit(0x00EE, &token),
+            "clear" => self.emit(0x00E0, &token),
+            "hires" => self.emit(0x00FF, &token),
+            "lores" => self.emit(0x00FE, &token),
+            "exit" => self.emit(0x00FD, &token),
+            "scroll-down" => {
+                let n = self.nibble(&token)?;
+                self.emit(0x00C0 | n as u16, &token);
+            }
+            "scroll-up" => {
+                let n = self.nibble(&token)?;
+                self.emit(0x00D0 | n as u16, &token);
+            }
+            "scroll-left" => self.emit(0x00FC, &token),
+            "scroll-right" => self.emit(0x00FB, &token),
+            "audio" => self.emit(0xF002, &token),
+            "plane" => {
+                let n = self.nibble(&token)?;
+                self.emit(0xF001 | x(n), &token);
+            }
+            "bcd" => {
+                let vx = self.expect_register(&token)?;
+                self.emit(0xF033 | x(vx), &token);
+            }
+            "save" | "load" => {
+                let vx = self.expect_register(&token)?;
+                if self.peek() == Some("-") {
+                    self.next();
+                    let vy = self.expect_register(&token)?;
+                    let low = if token.text == "save" { 0x2 } else { 0x3 };
+                    self.emit(0x5000 | xy(vx, vy) | low, &token);
+                } else {
+                    let low = if token.text == "save" { 0x55 } else { 0x65 };
+                    self.emit(0xF000 | x(vx) | low, &token);
+                }
+            }
+            "saveflags" => {
+                let vx = self.expect_register(&token)?;
+                self.emit(0xF075 | x(vx), &token);
+            }
+            "loadflags" => {
+                let vx = self.expect_register(&token)?;
+                self.emit(0xF085 | x(vx), &token);
+            }
+            "sprite" => {
+                let vx = self.expect_register(&token)?;
+                let vy = self.expect_register(&token)?;
+                let n = self.nibble(&token)?;
+                self.emit(0xD000 | xy(vx, vy) | n as u16, &token);
+            }
+            "jump" => self.emit_address(0x1000, &token, Fixup::Address)?,
+            "jump0" => self.emit_address(0xB000, &token, Fixup::Address)?,
+            "native" => self.emit_address(0x0000, &token, Fixup::Address)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect_text(&token, ":=")?;
+                let vx = self.expect_register(&token)?;
+                let low = match token.text.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | x(vx) | low, &token);
+            }
+            "i" => {
+                let operator = self.expect(&token)?;
+                match operator.text.as_str() {
+                    ":=" => match self.peek() {
+                        Some("hex") => {
+                            self.next();
+                            let vx = self.expect_register(&token)?;
+                            self.emit(0xF029 | x(vx), &token);
+                        }
+                        Some("bighex") => {
+                            self.next();
+                            let vx = self.expect_register(&token)?;
+                            self.emit(0xF030 | x(vx), &token);
+                        }
+                        Some("long") => {
+                            self.next();
+                            self.emit_address(0xF000, &token, Fixup::Long)?;
+                        }
+                        _ => self.emit_address(0xA000, &token, Fixup::Address)?,
+                    },
+                    "+=" => {
+                        let vx = self.expect_register(&token)?;
+                        self.emit(0xF01E | x(vx), &token);
+                    }
+                    _ => return Err(operator.error(format!("unknown operator `{}` for i", operator.text))),
+                }
+            }
+            "if" => {
+                let condition = self.condition(&token)?;
+                let keyword = self.expect(&token)?;
+                match keyword.text.as_str() {
+                    "then" => self.skip(&condition, false, &token),
+                    "begin" => {
+                        self.skip(&condition, true, &token);
+                        self.control.push((Control::If(self.here), token.clone()));
+                        self.emit(0x1000, &token);
+                    }
+                    _ => return Err(keyword.error(format!("expected `then` or `begin`, found `{}`", keyword.text))),
+                }
+            }
+            "else" => match self.control.pop() {
+                Some((Control::If(branch), _)) => {
+                    self.control.push((Control::Else(self.here), token.clone()));
+                    self.emit(0x1000, &token);
+                    self.patch(branch, Fixup::Address, self.here, &token)?;
+                }
+                _ => return Err(token.error("`else` without a matching `if ... begin`")),
+            },
+            "end" => match self.control.pop() {
+                Some((Control::If(branch) | Control::Else(branch), _)) => {
+                    self.patch(branch, Fixup::Address, self.here, &token)?;
+                }
+                _ => return Err(token.error("`end` without a matching `if ... begin`")),
+            },
+            "loop" => self.control.push((Control::Loop { start: self.here, exits: Vec::new() }, token)),
+            "while" => {
+                let condition = self.condition(&token)?;
+                self.skip(&condition, true, &token);
+                let exit = self.here;
+                self.emit(0x1000, &token);
+                match self.control.iter_mut().rev().find_map(|(control, _)| match control {
+                    Control::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(exit),
+                    None => return Err(token.error("`while` outside of a loop")),
+                }
+            }
+            "again" => match self.control.pop() {
+                Some((Control::Loop { start, exits }, _)) => {
+                    self.emit(0x1000, &token);
+                    self.patch(self.here - 2, Fixup::Address, start, &token)?;
+                    for exit in exits {
+                        self.patch(exit, Fixup::Address, self.here, &token)?;
+                    }
+                }
+                _ => return Err(token.error("`again` without a matching `loop`")),
+            },
+            _ if self.macros.contains_key(&token.text) => self.expand_macro(&token)?,
+            _ => match self.number(&token)? {
+                // Bare numbers are data, which is how sprites are written.
+                Some(value) if !self.labels.contains_key(&token.text) => {
+                    let byte = Self::check_range(&token, value, -128, 255)? as u8;
+                    self.emit_byte(byte);
+                }
+                // Anything else names a subroutine.
+                _ if token.text.starts_with(':') => {
+                    return Err(token.error(format!("unknown directive `{}`", token.text)));
+                }
+                _ => self.emit_address_of(0x2000, &token, token.clone(), Fixup::Address)?,
+            },
+        }
+        Ok(())
+    }
+
+    fn assignment(&mut self, vx: u8, token: &Token) -> Result<(), OctoError> {
+        let operator = self.expect(token)?;
+        let operand = self.expect(token)?;
+        let x = (vx as u16) << 8;
+        let register = self.register(&operand);
+
+        let opcode = match (operator.text.as_str(), register) {
+            (":=", Some(vy)) => 0x8000 | x | (vy as u16) << 4,
+            (":=", None) if operand.text == "key" => 0xF00A | x,
+            (":=", None) if operand.text == "delay" => 0xF007 | x,
+            (":=", None) if operand.text == "random" => 0xC000 | x | self.byte(token)? as u16,
+            (":=", None) => 0x6000 | x | self.operand_byte(&operand)? as u16,
+            ("+=", Some(vy)) => 0x8004 | x | (vy as u16) << 4,
+            ("+=", None) => 0x7000 | x | self.operand_byte(&operand)? as u16,
+            ("-=", Some(vy)) => 0x8005 | x | (vy as u16) << 4,
+            ("-=", None) => 0x7000 | x | (self.operand_byte(&operand)?.wrapping_neg()) as u16,
+            ("=-", Some(vy)) => 0x8007 | x | (vy as u16) << 4,
+            ("|=", Some(vy)) => 0x8001 | x | (vy as u16) << 4,
+            ("&=", Some(vy)) => 0x8002 | x | (vy as u16) << 4,
+            ("^=", Some(vy)) => 0x8003 | x | (vy as u16) << 4,
+            (">>=", Some(vy)) => 0x8006 | x | (vy as u16) << 4,
+            ("<<=", Some(vy)) => 0x800E | x | (vy as u16) << 4,
+            _ => {
+                return Err(operator.error(format!("cannot use `{}` with `{}`", operator.text, operand.text)));
+            }
+        };
+        self.emit(opcode, token);
+        Ok(())
+    }
+
+    fn operand_byte(&mut self, operand: &Token) -> Result<u8, OctoError> {
+        let value = self.number(operand)?.ok_or_else(|| operand.error(format!("undefined name `{}`", operand.text)))?;
+        Self::check_range(operand, value, -128, 255).map(|value| value as u8)
+    }
+
+    fn condition(&mut self, token: &Token) -> Result<Condition, OctoError> {
+        let register = self.expect_register(token)?;
+        let operator = self.expect(token)?;
+        let comparison = match operator.text.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessEqual,
+            ">=" => Comparison::GreaterEqual,
+            "key" => return Ok(Condition { register, comparison: Comparison::Key, operand: None }),
+            "-key" => return Ok(Condition { register, comparison: Comparison::NotKey, operand: None }),
+            _ => return Err(operator.error(format!("unknown comparison `{}`", operator.text))),
+        };
+
+        let value = self.expect(token)?;
+        let operand = match self.register(&value) {
+            Some(vy) => Operand::Register(vy),
+            None => Operand::Number(self.operand_byte(&value)? as f64),
+        };
+        Ok(Condition { register, comparison, operand: Some(operand) })
+    }
+
+    // Emits instructions that skip the next one when the condition equals `when`.
+    fn skip(&mut self, condition: &Condition, when: bool, token: &Token) {
+        let x = (condition.register as u16) << 8;
+        let (equal, comparison) = match condition.comparison {
+            Comparison::Equal => (when, None),
+            Comparison::NotEqual => (!when, None),
+            Comparison::Key => {
+                self.emit(if when { 0xE09E } else { 0xE0A1 } | x, token);
+                return;
+            }
+            Comparison::NotKey => {
+                self.emit(if when { 0xE0A1 } else { 0xE09E } | x, token);
+                return;
+            }
+            comparison => (false, Some(comparison)),
+        };
+
+        let Some(comparison) = comparison else {
+            match condition.operand {
+                Some(Operand::Register(vy)) => {
+                    self.emit(if equal { 0x5000 } else { 0x9000 } | x | (vy as u16) << 4, token);
+                }
+                Some(Operand::Number(n)) => self.emit(if equal { 0x3000 } else { 0x4000 } | x | n as u16, token),
+                None => unreachable!("comparison without an operand"),
+            }
+            return;
+        };
+
+        // Ordered comparisons compute a - b or b - a into vf; the borrow flag is
+        // 1 exactly when no borrow happened.
+        let swapped = matches!(comparison, Comparison::Greater | Comparison::LessEqual);
+        let flag_when_true = matches!(comparison, Comparison::LessEqual | Comparison::GreaterEqual) as u16;
+        let vf = (VF as u16) << 8;
+        match (&condition.operand, swapped) {
+            (Some(Operand::Register(vy)), false) => {
+                self.emit(0x8000 | vf | x >> 4, token);
+                self.emit(0x8005 | vf | (*vy as u16) << 4, token);
+            }
+            (Some(Operand::Register(vy)), true) => {
+                self.emit(0x8000 | vf | (*vy as u16) << 4, token);
+                self.emit(0x8005 | vf | x >> 4, token);
+            }
+            (Some(Operand::Number(n)), false) => {
+                self.emit(0x6000 | vf | *n as u16, token);
+                self.emit(0x8007 | vf | x >> 4, token);
+            }
+            (Some(Operand::Number(n)), true) => {
+                self.emit(0x6000 | vf | *n as u16, token);
+                self.emit(0x8005 | vf | x >> 4, token);
+            }
+            (None, _) => unreachable!("comparison without an operand"),
+        }
+        let flag = if when { flag_when_true } else { 1 - flag_when_true };
+        self.emit(0x3000 | vf | flag, token);
+    }
+
+    fn define_macro(&mut self, token: &Token) -> Result<(), OctoError> {
+        let name = self.expect(token)?;
+        if self.macros.contains_key(&name.text) {
+            return Err(name.error(format!("macro `{}` is already defined", name.text)));
+        }
+
+        let mut args = Vec::new();
+        loop {
+            let arg = self.expect(token)?;
+            if arg.text == "{" {
+                break;
+            }
+            args.push(arg.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let next = self.expect(token)?;
+            match next.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 1 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(next);
+        }
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), OctoError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(token.error(format!("macro `{}` expands forever", token.text)));
+        }
+
+        let arg_count = self.macros[&token.text].args.len();
+        let mut values = Vec::with_capacity(arg_count);
+        for _ in 0..arg_count {
+            values.push(self.expect(token)?.text);
+        }
+        let definition = &self.macros[&token.text];
+        let bindings: HashMap<&str, &str> =
+            definition.args.iter().map(String::as_str).zip(values.iter().map(String::as_str)).collect();
+
+        let expanded: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|body_token| match bindings.get(body_token.text.as_str()) {
+                Some(value) => Token { text: value.to_string(), ..body_token.clone() },
+                None => body_token.clone(),
+            })
+            .collect();
+        self.pending.extend(expanded.into_iter().rev());
+        Ok(())
+    }
+
+    // Evaluates the tokens up to the matching `}`. Like Octo, operators have no
+    // precedence and group from the right.
+    fn calc_block(&mut self, open: &Token) -> Result<f64, OctoError> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.expect(open)?;
+            if token.text == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+        let mut position = 0;
+        let value = self.calc_expression(&tokens, &mut position, open)?;
+        if let Some(extra) = tokens.get(position) {
+            return Err(extra.error(format!("unexpected `{}` in expression", extra.text)));
+        }
+        Ok(value)
+    }
+
+    fn calc_expression(&self, tokens: &[Token], position: &mut usize, open: &Token) -> Result<f64, OctoError> {
+        let token = tokens.get(*position).ok_or_else(|| open.error("expected an expression"))?;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|a| -a),
+            "~" => Some(|a| !(a as i64) as f64),
+            "!" => Some(|a| (a == 0.0) as i64 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(function) = unary {
+            *position += 1;
+            return Ok(function(self.calc_expression(tokens, position, open)?));
+        }
+        if token.text == "@" {
+            *position += 1;
+            let address = self.calc_expression(tokens, position, open)? as usize;
+            let byte = address.checked_sub(ROM_START).and_then(|offset| self.rom.get(offset));
+            return Ok(byte.copied().unwrap_or(0) as f64);
+        }
+
+        let lhs = self.calc_term(tokens, position, open)?;
+        let Some(operator) = tokens.get(*position) else {
+            return Ok(lhs);
+        };
+        let binary: fn(f64, f64) -> f64 = match operator.text.as_str() {
+            "+" => |a, b| a + b,
+            "-" => |a, b| a - b,
+            "*" => |a, b| a * b,
+            "/" => |a, b| a / b,
+            "%" => |a, b| a % b,
+            "pow" => f64::powf,
+            "min" => f64::min,
+            "max" => f64::max,
+            "&" => |a, b| ((a as i64) & (b as i64)) as f64,
+            "|" => |a, b| ((a as i64) | (b as i64)) as f64,
+            "^" => |a, b| ((a as i64) ^ (b as i64)) as f64,
+            "<<" => |a, b| ((a as i64) << (b as i64 & 63)) as f64,
+            ">>" => |a, b| ((a as i64) >> (b as i64 & 63)) as f64,
+            "<" => |a, b| (a < b) as i64 as f64,
+            ">" => |a, b| (a > b) as i64 as f64,
+            "<=" => |a, b| (a <= b) as i64 as f64,
+            ">=" => |a, b| (a >= b) as i64 as f64,
+            "==" => |a, b| (a == b) as i64 as f64,
+            "!=" => |a, b| (a != b) as i64 as f64,
+            _ => return Err(operator.error(format!("unknown operator `{}`", operator.text))),
+        };
+        *position += 1;
+        Ok(binary(lhs, self.calc_expression(tokens, position, open)?))
+    }
+
+    fn calc_term(&self, tokens: &[Token], position: &mut usize, open: &Token) -> Result<f64, OctoError> {
+        let token = tokens.get(*position).ok_or_else(|| open.error("expected an expression"))?;
+        *position += 1;
+
+        if token.text == "(" {
+            let value = self.calc_expression(tokens, position, open)?;
+            match tokens.get(*position) {
+                Some(close) if close.text == ")" => *position += 1,
+                _ => return Err(token.error("unclosed `(`")),
+            }
+            return Ok(value);
+        }
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value);
+        }
+        match token.text.as_str() {
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(PI),
+            "E" => Ok(E),
+            name => match (self.constants.get(name), self.labels.get(name)) {
+                (Some(&value), _) => Ok(value),
+                (None, Some(&address)) => Ok(address as f64),
+                (None, None) => Err(token.error(format!("undefined name `{}`", name))),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The program's words after the leading `jump main`.
+    fn words(source: &str) -> Vec<u16> {
+        let rom = compile(source, "test.8o").unwrap().rom;
+        rom[2..].chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect()
+    }
+
+    #[test]
+    fn if_then_skips_the_next_statement() {
+        let source = ": main\nif v0 == 5 then v1 := 2\nif v0 != v2 then v1 := 3\nif v0 key then v1 := 4\n";
+        assert_eq!(words(source), [0x4005, 0x6102, 0x5020, 0x6103, 0xE0A1, 0x6104]);
+    }
+
+    #[test]
+    fn if_begin_else_end_jumps_around_branches() {
+        let source = ": main\nif v0 == 5 begin\nv1 := 1\nelse\nv1 := 2\nend\n";
+        assert_eq!(words(source), [0x3005, 0x120A, 0x6101, 0x120C, 0x6102]);
+        let source = ": main\nif v0 == 5 begin\nv1 := 1\nend\n";
+        assert_eq!(words(source), [0x3005, 0x1208, 0x6101]);
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let source = ": main\nloop\nv0 += 1\nwhile v0 != 10\nagain\n";
+        assert_eq!(words(source), [0x7001, 0x400A, 0x120A, 0x1202]);
+    }
+
+    #[test]
+    fn ordered_comparisons_use_vf() {
+        assert_eq!(words(": main\nif v0 < v1 then v2 := 1\n"), [0x8F00, 0x8F15, 0x3F01, 0x6201]);
+    }
+
+    #[test]
+    fn source_map_points_at_lines() {
+        let program = compile(": main\nif v0 == 5 begin\n  v1 := 1\nelse\n  v1 := 2\nend\n", "test.8o").unwrap();
+        let line = |address| program.source_map.lookup(address).map(|location| location.line);
+        assert_eq!(line(0x200), Some(1));
+        assert_eq!(line(0x202), Some(2));
+        assert_eq!(line(0x206), Some(3));
+        assert_eq!(line(0x208), Some(4));
+        assert_eq!(line(0x20A), Some(5));
+        assert_eq!(line(0x20C), None);
+        assert_eq!(program.source_map.lookup(0x206).unwrap().to_string(), "test.8o:3");
+    }
+
+    #[test]
+    fn unbalanced_blocks_are_errors() {
+        let error = compile(": main\nloop\nv0 += 1\n", "test.8o").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 1));
+        assert!(compile(": main\nend\n", "test.8o").is_err());
+        assert!(compile("v0 := 1\n", "test.8o").is_err());
+    }
+}
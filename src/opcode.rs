@@ -1,12 +1,38 @@
+use crate::platform::Platform;
+use std::fmt;
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     CLS,                          // 00E0
     RET,                          // 00EE
+    SCD_nibble(u8),               // 00Cn
+    SCU_nibble(u8),               // 00Dn
+    SCRU_nibble(u8),              // 00Bn
+    SCR,                          // 00FB
+    SCL,                          // 00FC
+    EXIT,                         // 00FD
+    LOW,                          // 00FE
+    HIGH,                         // 00FF
+    MEGAOFF,                      // 0010
+    MEGAON,                       // 0011
+    LDHI_I(u32),                  // 01nn nnnn
+    LDPAL(u8),                    // 02nn
+    SPRW(u8),                     // 03nn
+    SPRH(u8),                     // 04nn
+    ALPHA(u8),                    // 05nn
+    DIGISND(u8),                  // 060n
+    STOPSND,                      // 0700
+    BMODE(u8),                    // 080n
+    CCOL(u8),                     // 09nn
     SYS(u16),                     // 0nnn
     JP(u16),                      // 1nnn
     CALL(u16),                    // 2nnn
     SE_Vx_byte(u8, u8),           // 3xkk
     SNE_Vx_byte(u8, u8),          // 4xkk
     SE_Vx_Vy(u8, u8),             // 5xy0
+    SAVE_Vx_Vy(u8, u8),           // 5xy2
+    LOAD_Vx_Vy(u8, u8),           // 5xy3
     LD_Vx_byte(u8, u8),           // 6xkk
     ADD_Vx_byte(u8, u8),          // 7xkk
     LD_Vx_Vy(u8, u8),             // 8xy0
@@ -15,9 +41,9 @@ pub enum Opcode {
     XOR_Vx_Vy(u8, u8),            // 8xy3
     ADD_Vx_Vy(u8, u8),            // 8xy4
     SUB_Vx_Vy(u8, u8),            // 8xy5
-    SHR_Vx(u8),                   // 8xy6
+    SHR_Vx_Vy(u8, u8),            // 8xy6
     SUBN_Vx_Vy(u8, u8),           // 8xy7
-    SHL_Vx(u8),                   // 8xyE
+    SHL_Vx_Vy(u8, u8),            // 8xyE
     SNE_Vx_Vy(u8, u8),            // 9xy0
     LD_I_addr(u16),               // Annn
     JP_V0_addr(u16),              // Bnnn
@@ -34,5 +60,448 @@ pub enum Opcode {
     LD_B_Vx(u8),                  // Fx33
     LD_I_Vx(u8),                  // Fx55
     LD_Vx_I(u8),                  // Fx65
+    LD_HF_Vx(u8),                 // Fx30
+    LD_R_Vx(u8),                  // Fx75
+    LD_Vx_R(u8),                  // Fx85
+    LD_I_long(u16),               // F000 nnnn
+    PLANE(u8),                    // Fn01
+    AUDIO,                        // F002
+    PITCH_Vx(u8),                 // Fx3A
     INVALID(u16)                  // Invalid
 }
+
+impl Opcode {
+    // XO-CHIP F000 nnnn and MegaChip 01nn nnnn carry their address in the following word.
+    pub fn is_long(opcode: u16, platform: Platform) -> bool {
+        match platform {
+            Platform::XoChip => opcode == 0xF000,
+            Platform::MegaChip => opcode & 0xFF00 == 0x0100,
+            _ => false,
+        }
+    }
+
+    pub fn decode_long(opcode: u16, operand: u16) -> Opcode {
+        if opcode == 0xF000 {
+            Opcode::LD_I_long(operand)
+        } else {
+            Opcode::LDHI_I(((opcode as u32 & 0xFF) << 16) | operand as u32)
+        }
+    }
+
+    pub fn decode(opcode: u16, platform: Platform) -> Opcode {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let nnn = opcode & 0x0FFF;
+        let kk = (opcode & 0x00FF) as u8;
+        let n = (opcode & 0x000F) as u8;
+        // SUPER-CHIP and XO-CHIP extensions are invalid 2-byte opcodes on the platforms
+        // that predate them.
+        let super_chip = platform != Platform::Chip8;
+        let xo_chip = platform == Platform::XoChip;
+
+        if platform == Platform::MegaChip {
+            match opcode & 0xFF00 {
+                0x0000 if opcode == 0x0010 => return Opcode::MEGAOFF,
+                0x0000 if opcode == 0x0011 => return Opcode::MEGAON,
+                0x0000 if opcode & 0xFFF0 == 0x00B0 => return Opcode::SCRU_nibble(n),
+                0x0200 => return Opcode::LDPAL(kk),
+                0x0300 => return Opcode::SPRW(kk),
+                0x0400 => return Opcode::SPRH(kk),
+                0x0500 => return Opcode::ALPHA(kk),
+                0x0600 if kk <= 0x0F => return Opcode::DIGISND(n),
+                0x0700 if kk == 0 => return Opcode::STOPSND,
+                0x0800 if kk <= 0x0F => return Opcode::BMODE(n),
+                0x0900 => return Opcode::CCOL(kk),
+                _ => (),
+            }
+        }
+
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Opcode::CLS,
+                0x00EE => Opcode::RET,
+                0x00FB if super_chip => Opcode::SCR,
+                0x00FC if super_chip => Opcode::SCL,
+                0x00FD if super_chip => Opcode::EXIT,
+                0x00FE if super_chip => Opcode::LOW,
+                0x00FF if super_chip => Opcode::HIGH,
+                _ if super_chip && opcode & 0xFFF0 == 0x00C0 => Opcode::SCD_nibble(n),
+                0x00C0..=0x00CF | 0x00FB..=0x00FF => Opcode::INVALID(opcode),
+                _ if xo_chip && opcode & 0xFFF0 == 0x00D0 => Opcode::SCU_nibble(n),
+                _ => Opcode::SYS(nnn),
+            },
+            0x1000 => Opcode::JP(nnn),
+            0x2000 => Opcode::CALL(nnn),
+            0x3000 => Opcode::SE_Vx_byte(x, kk),
+            0x4000 => Opcode::SNE_Vx_byte(x, kk),
+            0x5000 => match n {
+                0x0 => Opcode::SE_Vx_Vy(x, y),
+                0x2 if xo_chip => Opcode::SAVE_Vx_Vy(x, y),
+                0x3 if xo_chip => Opcode::LOAD_Vx_Vy(x, y),
+                _ => Opcode::INVALID(opcode),
+            },
+            0x6000 => Opcode::LD_Vx_byte(x, kk),
+            0x7000 => Opcode::ADD_Vx_byte(x, kk),
+            0x8000 => match n {
+                0x0 => Opcode::LD_Vx_Vy(x, y),
+                0x1 => Opcode::OR_Vx_Vy(x, y),
+                0x2 => Opcode::AND_Vx_Vy(x, y),
+                0x3 => Opcode::XOR_Vx_Vy(x, y),
+                0x4 => Opcode::ADD_Vx_Vy(x, y),
+                0x5 => Opcode::SUB_Vx_Vy(x, y),
+                0x6 => Opcode::SHR_Vx_Vy(x, y),
+                0x7 => Opcode::SUBN_Vx_Vy(x, y),
+                0xE => Opcode::SHL_Vx_Vy(x, y),
+                _ => Opcode::INVALID(opcode),
+            },
+            0x9000 if n == 0 => Opcode::SNE_Vx_Vy(x, y),
+            0xA000 => Opcode::LD_I_addr(nnn),
+            0xB000 => Opcode::JP_V0_addr(nnn),
+            0xC000 => Opcode::RND_Vx_byte(x, kk),
+            0xD000 => Opcode::DRW_Vx_Vy_nibble(x, y, n),
+            0xE000 => match kk {
+                0x9E => Opcode::SKP_Vx(x),
+                0xA1 => Opcode::SKNP_Vx(x),
+                _ => Opcode::INVALID(opcode),
+            },
+            0xF000 => match kk {
+                0x00 if x == 0 => Opcode::INVALID(opcode),
+                0x01 if xo_chip => Opcode::PLANE(x),
+                0x02 if xo_chip && x == 0 => Opcode::AUDIO,
+                0x3A if xo_chip => Opcode::PITCH_Vx(x),
+                0x07 => Opcode::LD_Vx_DT(x),
+                0x0A => Opcode::LD_Vx_K(x),
+                0x15 => Opcode::LD_DT_Vx(x),
+                0x18 => Opcode::LD_ST_Vx(x),
+                0x1E => Opcode::ADD_I_Vx(x),
+                0x29 => Opcode::LD_F_Vx(x),
+                0x33 => Opcode::LD_B_Vx(x),
+                0x55 => Opcode::LD_I_Vx(x),
+                0x65 => Opcode::LD_Vx_I(x),
+                0x30 if super_chip => Opcode::LD_HF_Vx(x),
+                0x75 if super_chip => Opcode::LD_R_Vx(x),
+                0x85 if super_chip => Opcode::LD_Vx_R(x),
+                _ => Opcode::INVALID(opcode),
+            },
+            _ => Opcode::INVALID(opcode),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Opcode::LD_I_long(_) | Opcode::LDHI_I(_) => 4,
+            _ => 2,
+        }
+    }
+
+    // Encodes the first word of the instruction; long instructions carry their operand in `encode_into`.
+    pub fn encode(&self) -> u16 {
+        let xy = |opcode: u16, x: u8, y: u8| opcode | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xkk = |opcode: u16, x: u8, kk: u8| opcode | (x as u16 & 0xF) << 8 | kk as u16;
+        let x = |opcode: u16, x: u8| opcode | (x as u16 & 0xF) << 8;
+
+        match *self {
+            Opcode::CLS => 0x00E0,
+            Opcode::RET => 0x00EE,
+            Opcode::SCD_nibble(n) => 0x00C0 | (n as u16 & 0xF),
+            Opcode::SCU_nibble(n) => 0x00D0 | (n as u16 & 0xF),
+            Opcode::SCRU_nibble(n) => 0x00B0 | (n as u16 & 0xF),
+            Opcode::SCR => 0x00FB,
+            Opcode::SCL => 0x00FC,
+            Opcode::EXIT => 0x00FD,
+            Opcode::LOW => 0x00FE,
+            Opcode::HIGH => 0x00FF,
+            Opcode::MEGAOFF => 0x0010,
+            Opcode::MEGAON => 0x0011,
+            Opcode::LDHI_I(nnnnnn) => 0x0100 | ((nnnnnn >> 16) as u16 & 0xFF),
+            Opcode::LDPAL(kk) => 0x0200 | kk as u16,
+            Opcode::SPRW(kk) => 0x0300 | kk as u16,
+            Opcode::SPRH(kk) => 0x0400 | kk as u16,
+            Opcode::ALPHA(kk) => 0x0500 | kk as u16,
+            Opcode::DIGISND(n) => 0x0600 | (n as u16 & 0xF),
+            Opcode::STOPSND => 0x0700,
+            Opcode::BMODE(n) => 0x0800 | (n as u16 & 0xF),
+            Opcode::CCOL(kk) => 0x0900 | kk as u16,
+            Opcode::SYS(nnn) => nnn & 0x0FFF,
+            Opcode::JP(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Opcode::CALL(nnn) => 0x2000 | (nnn & 0x0FFF),
+            Opcode::SE_Vx_byte(vx, kk) => xkk(0x3000, vx, kk),
+            Opcode::SNE_Vx_byte(vx, kk) => xkk(0x4000, vx, kk),
+            Opcode::SE_Vx_Vy(vx, vy) => xy(0x5000, vx, vy),
+            Opcode::SAVE_Vx_Vy(vx, vy) => xy(0x5002, vx, vy),
+            Opcode::LOAD_Vx_Vy(vx, vy) => xy(0x5003, vx, vy),
+            Opcode::LD_Vx_byte(vx, kk) => xkk(0x6000, vx, kk),
+            Opcode::ADD_Vx_byte(vx, kk) => xkk(0x7000, vx, kk),
+            Opcode::LD_Vx_Vy(vx, vy) => xy(0x8000, vx, vy),
+            Opcode::OR_Vx_Vy(vx, vy) => xy(0x8001, vx, vy),
+            Opcode::AND_Vx_Vy(vx, vy) => xy(0x8002, vx, vy),
+            Opcode::XOR_Vx_Vy(vx, vy) => xy(0x8003, vx, vy),
+            Opcode::ADD_Vx_Vy(vx, vy) => xy(0x8004, vx, vy),
+            Opcode::SUB_Vx_Vy(vx, vy) => xy(0x8005, vx, vy),
+            Opcode::SHR_Vx_Vy(vx, vy) => xy(0x8006, vx, vy),
+            Opcode::SUBN_Vx_Vy(vx, vy) => xy(0x8007, vx, vy),
+            Opcode::SHL_Vx_Vy(vx, vy) => xy(0x800E, vx, vy),
+            Opcode::SNE_Vx_Vy(vx, vy) => xy(0x9000, vx, vy),
+            Opcode::LD_I_addr(nnn) => 0xA000 | (nnn & 0x0FFF),
+            Opcode::JP_V0_addr(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Opcode::RND_Vx_byte(vx, kk) => xkk(0xC000, vx, kk),
+            Opcode::DRW_Vx_Vy_nibble(vx, vy, n) => xy(0xD000, vx, vy) | (n as u16 & 0xF),
+            Opcode::SKP_Vx(vx) => x(0xE09E, vx),
+            Opcode::SKNP_Vx(vx) => x(0xE0A1, vx),
+            Opcode::LD_Vx_DT(vx) => x(0xF007, vx),
+            Opcode::LD_Vx_K(vx) => x(0xF00A, vx),
+            Opcode::LD_DT_Vx(vx) => x(0xF015, vx),
+            Opcode::LD_ST_Vx(vx) => x(0xF018, vx),
+            Opcode::ADD_I_Vx(vx) => x(0xF01E, vx),
+            Opcode::LD_F_Vx(vx) => x(0xF029, vx),
+            Opcode::LD_B_Vx(vx) => x(0xF033, vx),
+            Opcode::LD_I_Vx(vx) => x(0xF055, vx),
+            Opcode::LD_Vx_I(vx) => x(0xF065, vx),
+            Opcode::LD_HF_Vx(vx) => x(0xF030, vx),
+            Opcode::LD_R_Vx(vx) => x(0xF075, vx),
+            Opcode::LD_Vx_R(vx) => x(0xF085, vx),
+            Opcode::LD_I_long(_) => 0xF000,
+            Opcode::PLANE(n) => x(0xF001, n),
+            Opcode::AUDIO => 0xF002,
+            Opcode::PITCH_Vx(vx) => x(0xF03A, vx),
+            Opcode::INVALID(opcode) => opcode,
+        }
+    }
+
+    // Appends the big-endian bytes of the instruction, including the second word of long instructions.
+    pub fn encode_into(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.encode().to_be_bytes());
+        match *self {
+            Opcode::LD_I_long(nnnn) => bytes.extend_from_slice(&nnnn.to_be_bytes()),
+            Opcode::LDHI_I(nnnnnn) => bytes.extend_from_slice(&(nnnnnn as u16).to_be_bytes()),
+            _ => (),
+        }
+    }
+
+    // Returns the JP/CALL target, if this instruction transfers control to a fixed address.
+    pub fn branch_target(&self) -> Option<u16> {
+        match *self {
+            Opcode::JP(addr) | Opcode::CALL(addr) => Some(addr),
+            _ => None,
+        }
+    }
+
+    // The instruction name as printed by the disassembler, e.g. "DRW" or "LD".
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::CLS => "CLS",
+            Opcode::RET => "RET",
+            Opcode::SCD_nibble(..) => "SCD",
+            Opcode::SCU_nibble(..) => "SCU",
+            Opcode::SCRU_nibble(..) => "SCRU",
+            Opcode::SCR => "SCR",
+            Opcode::SCL => "SCL",
+            Opcode::EXIT => "EXIT",
+            Opcode::LOW => "LOW",
+            Opcode::HIGH => "HIGH",
+            Opcode::MEGAOFF => "MEGAOFF",
+            Opcode::MEGAON => "MEGAON",
+            Opcode::LDHI_I(..) => "LDHI",
+            Opcode::LDPAL(..) => "LDPAL",
+            Opcode::SPRW(..) => "SPRW",
+            Opcode::SPRH(..) => "SPRH",
+            Opcode::ALPHA(..) => "ALPHA",
+            Opcode::DIGISND(..) => "DIGISND",
+            Opcode::STOPSND => "STOPSND",
+            Opcode::BMODE(..) => "BMODE",
+            Opcode::CCOL(..) => "CCOL",
+            Opcode::SYS(..) => "SYS",
+            Opcode::JP(..) | Opcode::JP_V0_addr(..) => "JP",
+            Opcode::CALL(..) => "CALL",
+            Opcode::SE_Vx_byte(..) | Opcode::SE_Vx_Vy(..) => "SE",
+            Opcode::SNE_Vx_byte(..) | Opcode::SNE_Vx_Vy(..) => "SNE",
+            Opcode::SAVE_Vx_Vy(..) => "SAVE",
+            Opcode::LOAD_Vx_Vy(..) => "LOAD",
+            Opcode::LD_Vx_byte(..)
+            | Opcode::LD_Vx_Vy(..)
+            | Opcode::LD_I_addr(..)
+            | Opcode::LD_Vx_DT(..)
+            | Opcode::LD_Vx_K(..)
+            | Opcode::LD_DT_Vx(..)
+            | Opcode::LD_ST_Vx(..)
+            | Opcode::LD_F_Vx(..)
+            | Opcode::LD_B_Vx(..)
+            | Opcode::LD_I_Vx(..)
+            | Opcode::LD_Vx_I(..)
+            | Opcode::LD_HF_Vx(..)
+            | Opcode::LD_R_Vx(..)
+            | Opcode::LD_Vx_R(..)
+            | Opcode::LD_I_long(..) => "LD",
+            Opcode::ADD_Vx_byte(..) | Opcode::ADD_Vx_Vy(..) | Opcode::ADD_I_Vx(..) => "ADD",
+            Opcode::OR_Vx_Vy(..) => "OR",
+            Opcode::AND_Vx_Vy(..) => "AND",
+            Opcode::XOR_Vx_Vy(..) => "XOR",
+            Opcode::SUB_Vx_Vy(..) => "SUB",
+            Opcode::SHR_Vx_Vy(..) => "SHR",
+            Opcode::SUBN_Vx_Vy(..) => "SUBN",
+            Opcode::SHL_Vx_Vy(..) => "SHL",
+            Opcode::RND_Vx_byte(..) => "RND",
+            Opcode::DRW_Vx_Vy_nibble(..) => "DRW",
+            Opcode::SKP_Vx(..) => "SKP",
+            Opcode::SKNP_Vx(..) => "SKNP",
+            Opcode::PLANE(..) => "PLANE",
+            Opcode::AUDIO => "AUDIO",
+            Opcode::PITCH_Vx(..) => "PITCH",
+            Opcode::INVALID(..) => "DW",
+        }
+    }
+
+    // Formats the instruction, letting `name` replace addresses with labels.
+    pub fn to_asm(&self, name: impl Fn(u16) -> Option<String>) -> String {
+        let addr = |addr: u16| name(addr).unwrap_or_else(|| format!("0x{:03X}", addr));
+
+        match *self {
+            Opcode::CLS => "CLS".to_string(),
+            Opcode::RET => "RET".to_string(),
+            Opcode::SCD_nibble(n) => format!("SCD {}", n),
+            Opcode::SCU_nibble(n) => format!("SCU {}", n),
+            Opcode::SCRU_nibble(n) => format!("SCRU {}", n),
+            Opcode::SCR => "SCR".to_string(),
+            Opcode::SCL => "SCL".to_string(),
+            Opcode::EXIT => "EXIT".to_string(),
+            Opcode::LOW => "LOW".to_string(),
+            Opcode::HIGH => "HIGH".to_string(),
+            Opcode::MEGAOFF => "MEGAOFF".to_string(),
+            Opcode::MEGAON => "MEGAON".to_string(),
+            Opcode::LDHI_I(nnnnnn) => format!("LDHI I, 0x{:06X}", nnnnnn),
+            Opcode::LDPAL(kk) => format!("LDPAL {}", kk),
+            Opcode::SPRW(kk) => format!("SPRW {}", kk),
+            Opcode::SPRH(kk) => format!("SPRH {}", kk),
+            Opcode::ALPHA(kk) => format!("ALPHA 0x{:02X}", kk),
+            Opcode::DIGISND(n) => format!("DIGISND {}", n),
+            Opcode::STOPSND => "STOPSND".to_string(),
+            Opcode::BMODE(n) => format!("BMODE {}", n),
+            Opcode::CCOL(kk) => format!("CCOL 0x{:02X}", kk),
+            Opcode::SYS(nnn) => format!("SYS {}", addr(nnn)),
+            Opcode::JP(nnn) => format!("JP {}", addr(nnn)),
+            Opcode::CALL(nnn) => format!("CALL {}", addr(nnn)),
+            Opcode::SE_Vx_byte(x, kk) => format!("SE V{:X}, 0x{:02X}", x, kk),
+            Opcode::SNE_Vx_byte(x, kk) => format!("SNE V{:X}, 0x{:02X}", x, kk),
+            Opcode::SE_Vx_Vy(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            Opcode::SAVE_Vx_Vy(x, y) => format!("SAVE V{:X}, V{:X}", x, y),
+            Opcode::LOAD_Vx_Vy(x, y) => format!("LOAD V{:X}, V{:X}", x, y),
+            Opcode::LD_Vx_byte(x, kk) => format!("LD V{:X}, 0x{:02X}", x, kk),
+            Opcode::ADD_Vx_byte(x, kk) => format!("ADD V{:X}, 0x{:02X}", x, kk),
+            Opcode::LD_Vx_Vy(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Opcode::OR_Vx_Vy(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            Opcode::AND_Vx_Vy(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Opcode::XOR_Vx_Vy(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            Opcode::ADD_Vx_Vy(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Opcode::SUB_Vx_Vy(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            Opcode::SHR_Vx_Vy(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            Opcode::SUBN_Vx_Vy(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            Opcode::SHL_Vx_Vy(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            Opcode::SNE_Vx_Vy(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            Opcode::LD_I_addr(nnn) => format!("LD I, {}", addr(nnn)),
+            Opcode::JP_V0_addr(nnn) => format!("JP V0, {}", addr(nnn)),
+            Opcode::RND_Vx_byte(x, kk) => format!("RND V{:X}, 0x{:02X}", x, kk),
+            Opcode::DRW_Vx_Vy_nibble(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Opcode::SKP_Vx(x) => format!("SKP V{:X}", x),
+            Opcode::SKNP_Vx(x) => format!("SKNP V{:X}", x),
+            Opcode::LD_Vx_DT(x) => format!("LD V{:X}, DT", x),
+            Opcode::LD_Vx_K(x) => format!("LD V{:X}, K", x),
+            Opcode::LD_DT_Vx(x) => format!("LD DT, V{:X}", x),
+            Opcode::LD_ST_Vx(x) => format!("LD ST, V{:X}", x),
+            Opcode::ADD_I_Vx(x) => format!("ADD I, V{:X}", x),
+            Opcode::LD_F_Vx(x) => format!("LD F, V{:X}", x),
+            Opcode::LD_B_Vx(x) => format!("LD B, V{:X}", x),
+            Opcode::LD_I_Vx(x) => format!("LD [I], V{:X}", x),
+            Opcode::LD_Vx_I(x) => format!("LD V{:X}, [I]", x),
+            Opcode::LD_HF_Vx(x) => format!("LD HF, V{:X}", x),
+            Opcode::LD_R_Vx(x) => format!("LD R, V{:X}", x),
+            Opcode::LD_Vx_R(x) => format!("LD V{:X}, R", x),
+            Opcode::LD_I_long(nnnn) => format!("LD I, LONG {}", name(nnnn).unwrap_or_else(|| format!("0x{:04X}", nnnn))),
+            Opcode::PLANE(n) => format!("PLANE {}", n),
+            Opcode::AUDIO => "AUDIO".to_string(),
+            Opcode::PITCH_Vx(x) => format!("PITCH V{:X}", x),
+            Opcode::INVALID(opcode) => format!("DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_asm(|_| None))
+    }
+}
+
+// Decodes the instruction at the start of `bytes`, or returns None if it is cut short.
+pub fn decode(bytes: &[u8], platform: Platform) -> Option<Opcode> {
+    let word = |offset: usize| {
+        let high = *bytes.get(offset)? as u16;
+        let low = *bytes.get(offset + 1)? as u16;
+        Some((high << 8) | low)
+    };
+
+    let opcode = word(0)?;
+    if Opcode::is_long(opcode, platform) {
+        Some(Opcode::decode_long(opcode, word(2)?))
+    } else {
+        Some(Opcode::decode(opcode, platform))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLATFORMS: [Platform; 4] = [Platform::Chip8, Platform::SuperChip, Platform::XoChip, Platform::MegaChip];
+
+    #[test]
+    fn every_word_encodes_back_to_itself() {
+        for platform in PLATFORMS {
+            for word in 0..=0xFFFF {
+                if Opcode::is_long(word, platform) {
+                    continue;
+                }
+                let opcode = Opcode::decode(word, platform);
+                assert_eq!(opcode.encode(), word, "{:04X} on {}", word, platform);
+                let super_chip_only = matches!(word, 0x00C0..=0x00CF | 0x00FB..=0x00FF)
+                    || word & 0xF000 == 0xF000 && matches!(word & 0xFF, 0x30 | 0x75 | 0x85);
+                if platform == Platform::Chip8 && super_chip_only {
+                    assert_eq!(opcode, Opcode::INVALID(word), "{:04X} on {}", word, platform);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_instructions_round_trip() {
+        for (opcode, platform) in [(Opcode::LD_I_long(0xBEEF), Platform::XoChip), (Opcode::LDHI_I(0x12_3456), Platform::MegaChip)] {
+            let mut bytes = Vec::new();
+            opcode.encode_into(&mut bytes);
+            assert_eq!(bytes.len(), opcode.size());
+            assert_eq!(decode(&bytes, platform), Some(opcode));
+            assert_eq!(decode(&bytes[..2], platform), None);
+        }
+    }
+
+    #[test]
+    fn formats_assembly() {
+        assert_eq!(Opcode::decode(0xD125, Platform::Chip8).to_string(), "DRW V1, V2, 5");
+        assert_eq!(Opcode::decode(0x2ABC, Platform::Chip8).to_string(), "CALL 0xABC");
+        assert_eq!(Opcode::JP(0x300).to_asm(|_| Some("main".to_string())), "JP main");
+        assert_eq!(Opcode::decode(0xF055, Platform::Chip8).to_string(), "LD [I], V0");
+        assert_eq!(Opcode::decode(0x8AB9, Platform::Chip8).to_string(), "DW 0x8AB9");
+    }
+
+    #[test]
+    fn xo_chip_opcodes_are_gated_on_platform() {
+        assert_eq!(Opcode::decode(0x00D4, Platform::SuperChip), Opcode::SYS(0x0D4));
+        for opcode in [0x5122, 0x5123, 0xF201, 0xF002, 0xF13A, 0xF000] {
+            assert_eq!(Opcode::decode(opcode, Platform::Chip8), Opcode::INVALID(opcode), "{:04X}", opcode);
+            assert_eq!(Opcode::decode(opcode, Platform::SuperChip), Opcode::INVALID(opcode), "{:04X}", opcode);
+            assert!(!Opcode::is_long(opcode, Platform::SuperChip));
+        }
+        assert_eq!(Opcode::decode(0x5122, Platform::XoChip), Opcode::SAVE_Vx_Vy(1, 2));
+        assert_eq!(Opcode::decode(0x00D4, Platform::XoChip), Opcode::SCU_nibble(4));
+        assert!(Opcode::is_long(0xF000, Platform::XoChip));
+        assert_eq!(decode(&[0xF0, 0x00, 0x12, 0x34], Platform::XoChip), Some(Opcode::LD_I_long(0x1234)));
+        assert_eq!(decode(&[0xF0, 0x00, 0x12, 0x34], Platform::Chip8), Some(Opcode::INVALID(0xF000)));
+    }
+}
@@ -0,0 +1,49 @@
+pub const DEFAULT_PALETTE: &str = "000000,FFFFFF,AAAAAA,555555";
+
+// Colours for each combination of the two XO-CHIP bitplanes: off, plane 1, plane 2, both.
+#[derive(Clone)]
+pub struct Palette {
+    colors: [[u8; 4]; 4],
+}
+
+impl Palette {
+    // Accepts a preset name or 2 or 4 comma-separated RGB hex colours. With two colours
+    // the second plane reuses the foreground.
+    pub fn parse(spec: &str) -> Result<Palette, String> {
+        let spec = match spec.to_ascii_lowercase().as_str() {
+            "default" | "grey" | "gray" => DEFAULT_PALETTE.to_string(),
+            "amber" => "1A0F00,FFB000,B37B00,664600".to_string(),
+            "green" => "0F1F0F,33FF66,22AA44,115522".to_string(),
+            "lcd" => "9BBC0F,0F380F,306230,8BAC0F".to_string(),
+            _ => spec.to_string(),
+        };
+
+        let colors = spec
+            .split(',')
+            .map(|color| Self::parse_color(color.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match colors[..] {
+            [background, foreground] => Ok(Palette { colors: [background, foreground, foreground, foreground] }),
+            [background, plane1, plane2, both] => Ok(Palette { colors: [background, plane1, plane2, both] }),
+            _ => Err(format!("Expected 2 or 4 colours, got {}", colors.len())),
+        }
+    }
+
+    pub fn color(&self, planes: u8) -> [u8; 4] {
+        self.colors[planes as usize & 0b11]
+    }
+
+    fn parse_color(color: &str) -> Result<[u8; 4], String> {
+        let hex = color.trim_start_matches('#');
+        let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6);
+        let value = value.ok_or_else(|| format!("Invalid colour '{}', expected RRGGBB", color))?;
+        Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8, 0xFF])
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::parse(DEFAULT_PALETTE).expect("default palette is valid")
+    }
+}
@@ -0,0 +1,76 @@
+use crate::quirks::Quirks;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+    MegaChip,
+}
+
+impl Platform {
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0x10000,
+            Platform::MegaChip => 0x1000000,
+        }
+    }
+
+    // Stable numbering used by save states and movies.
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+            Platform::MegaChip => 3,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Platform> {
+        match id {
+            0 => Some(Platform::Chip8),
+            1 => Some(Platform::SuperChip),
+            2 => Some(Platform::XoChip),
+            3 => Some(Platform::MegaChip),
+            _ => None,
+        }
+    }
+
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::default(),
+            Platform::SuperChip | Platform::MegaChip => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks::XO_CHIP,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "super-chip" | "superchip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            "megachip" | "mega-chip" => Ok(Platform::MegaChip),
+            _ => Err(format!("Unknown platform '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+            Platform::MegaChip => "megachip",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,53 @@
+use chip8_emulator::constants::SAVES_DIR;
+use chip8_emulator::Quirks;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const PROFILES_FILE: &str = "quirks.cfg";
+
+// Uses the quirks requested on the command line, otherwise falls back to whatever was last
+// stored for the ROM. Requested quirks are only remembered when `remember` is set, so
+// headless runs never write to disk.
+pub fn resolve_quirks(rom_hash: u64, requested: Option<Quirks>, remember: bool) -> Option<Quirks> {
+    let hash = format!("{:016x}", rom_hash);
+    let mut profiles = load_profiles();
+
+    match requested {
+        Some(quirks) => {
+            if remember {
+                profiles.insert(hash, quirks.to_string());
+                if let Err(e) = save_profiles(&profiles) {
+                    eprintln!("Failed to save quirks profile: {}", e);
+                }
+            }
+            Some(quirks)
+        }
+        None => profiles.get(&hash).and_then(|quirks| quirks.parse().ok()),
+    }
+}
+
+fn profiles_path() -> PathBuf {
+    Path::new(SAVES_DIR).join(PROFILES_FILE)
+}
+
+fn load_profiles() -> BTreeMap<String, String> {
+    let contents = fs::read_to_string(profiles_path()).unwrap_or_default();
+
+    contents
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(hash, quirks)| (hash.to_string(), quirks.trim().to_string()))
+        .collect()
+}
+
+fn save_profiles(profiles: &BTreeMap<String, String>) -> io::Result<()> {
+    let contents: String = profiles
+        .iter()
+        .map(|(hash, quirks)| format!("{} {}\n", hash, quirks))
+        .collect();
+
+    fs::create_dir_all(SAVES_DIR)?;
+    fs::write(profiles_path(), contents)
+}
@@ -0,0 +1,168 @@
+use crate::platform::Platform;
+use std::fmt;
+use std::str::FromStr;
+
+pub(crate) const QUIRK_COUNT: u8 = 6;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool,
+    pub load_store_increments_i: bool,
+    pub vf_reset: bool,
+    pub jump_with_vx: bool,
+    pub clip_sprites: bool,
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        vf_reset: true,
+        jump_with_vx: false,
+        clip_sprites: true,
+        display_wait: true,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: true,
+        vf_reset: false,
+        jump_with_vx: true,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        vf_reset: false,
+        jump_with_vx: true,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        vf_reset: false,
+        jump_with_vx: false,
+        clip_sprites: false,
+        display_wait: false,
+    };
+
+    // One bit per quirk in declaration order, as stored in save states and movies.
+    pub(crate) fn bits(&self) -> u8 {
+        [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.vf_reset,
+            self.jump_with_vx,
+            self.clip_sprites,
+            self.display_wait,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (index, &enabled)| bits | (enabled as u8) << index)
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Quirks {
+        let enabled = |index: u8| bits >> index & 1 == 1;
+        Quirks {
+            shift_uses_vy: enabled(0),
+            load_store_increments_i: enabled(1),
+            vf_reset: enabled(2),
+            jump_with_vx: enabled(3),
+            clip_sprites: enabled(4),
+            display_wait: enabled(5),
+        }
+    }
+
+    // Platform names give the same quirks as selecting that platform, so `--quirks chip8`
+    // and `--platform chip8` agree.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        if let Ok(platform) = name.parse::<Platform>() {
+            return Some(platform.default_quirks());
+        }
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac-vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            _ => None,
+        }
+    }
+
+    fn flags_mut(&mut self) -> [(&'static str, &mut bool); 6] {
+        [
+            ("shift-vy", &mut self.shift_uses_vy),
+            ("load-store-i", &mut self.load_store_increments_i),
+            ("vf-reset", &mut self.vf_reset),
+            ("jump-vx", &mut self.jump_with_vx),
+            ("clip", &mut self.clip_sprites),
+            ("display-wait", &mut self.display_wait),
+        ]
+    }
+}
+
+// Accepts either a preset name or a comma-separated list of enabled quirks, e.g. `clip,vf-reset`.
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(quirks) = Quirks::preset(s) {
+            return Ok(quirks);
+        }
+
+        let mut quirks = Quirks::default();
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(quirks);
+        }
+        for name in s.split(',').map(str::trim) {
+            let (_, flag) = quirks
+                .flags_mut()
+                .into_iter()
+                .find(|(flag, _)| flag.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("Unknown quirk or preset '{}'", name))?;
+            *flag = true;
+        }
+        Ok(quirks)
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut quirks = *self;
+        let enabled: Vec<&str> = quirks
+            .flags_mut()
+            .into_iter()
+            .filter(|(_, flag)| **flag)
+            .map(|(name, _)| name)
+            .collect();
+
+        if enabled.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", enabled.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_presets_match_platform_defaults() {
+        for platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip, Platform::MegaChip] {
+            assert_eq!(platform.to_string().parse::<Quirks>(), Ok(platform.default_quirks()));
+        }
+        assert_eq!("vip".parse::<Quirks>(), Ok(Quirks::COSMAC_VIP));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for quirks in [Quirks::default(), Quirks::COSMAC_VIP, Quirks::CHIP_48, Quirks::XO_CHIP] {
+            assert_eq!(quirks.to_string().parse::<Quirks>(), Ok(quirks));
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use crate::constants::TIMER_FREQUENCY;
+use crate::cpu::{CPU, MEMORY_PAGE_SIZE};
+use std::collections::VecDeque;
+use std::ops::Range;
+
+pub const DEFAULT_REWIND_SECONDS: u32 = 10;
+pub const DEFAULT_REWIND_MEMORY: usize = 64 * 1024 * 1024;
+
+// Unchanged stretches shorter than this are folded into the surrounding literal run,
+// since each run costs a few bytes of framing.
+const MIN_GAP: usize = 8;
+
+// Keeps one snapshot per frame so execution can be stepped backwards. Only the newest
+// snapshot is stored whole; every older frame is a patch that turns the snapshot after it
+// back into that frame, so the oldest frames can be dropped without touching the rest.
+// Memory is kept apart from the rest of the machine and only the pages the CPU wrote to
+// are compared, so a frame costs the same on 4 KiB CHIP-8 and 16 MiB MegaChip.
+pub struct Rewind {
+    machine: Option<Vec<u8>>,
+    memory: Vec<u8>,
+    // The CPU's memory write count when `memory` was last brought up to date.
+    memory_writes: u64,
+    patches: VecDeque<Patch>,
+    max_frames: usize,
+    memory_budget: usize,
+    patch_bytes: usize,
+}
+
+struct Patch {
+    machine: Vec<u8>,
+    // Previous contents of every memory page that changed during the frame.
+    pages: Vec<(usize, Box<[u8]>)>,
+}
+
+impl Patch {
+    fn size(&self) -> usize {
+        self.machine.len() + self.pages.iter().map(|(_, page)| page.len() + size_of::<usize>()).sum::<usize>()
+    }
+}
+
+impl Rewind {
+    pub fn new(max_frames: usize, memory_budget: usize) -> Self {
+        Rewind {
+            machine: None,
+            memory: Vec::new(),
+            memory_writes: 0,
+            patches: VecDeque::new(),
+            max_frames,
+            memory_budget,
+            patch_bytes: 0,
+        }
+    }
+
+    pub fn with_seconds(seconds: u32, memory_budget: usize) -> Self {
+        Self::new((seconds * TIMER_FREQUENCY) as usize, memory_budget)
+    }
+
+    // Number of frames that can currently be rewound.
+    pub fn len(&self) -> usize {
+        self.patches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.machine = None;
+        self.memory = Vec::new();
+        self.patches.clear();
+        self.patch_bytes = 0;
+    }
+
+    // Records the state at the end of a frame.
+    pub fn record(&mut self, cpu: &CPU) {
+        let machine = cpu.machine_snapshot();
+        let previous = match self.machine.take() {
+            // A state with a different memory size was loaded; history can't cross that.
+            Some(previous) if self.memory.len() == cpu.memory().len() => previous,
+            _ => {
+                self.clear();
+                self.memory = cpu.memory().to_vec();
+                self.memory_writes = cpu.memory_writes();
+                self.machine = Some(machine);
+                return;
+            }
+        };
+
+        let mut pages = Vec::new();
+        for page in cpu.pages_written_since(self.memory_writes) {
+            let range = page_range(page, self.memory.len());
+            let current = &cpu.memory()[range.clone()];
+            if current != &self.memory[range.clone()] {
+                pages.push((page, self.memory[range.clone()].into()));
+                self.memory[range].copy_from_slice(current);
+            }
+        }
+        self.memory_writes = cpu.memory_writes();
+
+        let patch = Patch { machine: diff(&machine, &previous), pages };
+        self.patch_bytes += patch.size();
+        self.patches.push_back(patch);
+        self.machine = Some(machine);
+
+        while self.patches.len() > self.max_frames || self.patch_bytes + self.snapshot_bytes() > self.memory_budget {
+            let Some(oldest) = self.patches.pop_front() else {
+                break;
+            };
+            self.patch_bytes -= oldest.size();
+        }
+    }
+
+    // Steps the CPU back up to `frames` recorded frames and returns how many it went back.
+    pub fn rewind(&mut self, cpu: &mut CPU, frames: usize) -> usize {
+        let Some(mut machine) = self.machine.take() else {
+            return 0;
+        };
+        if self.memory.len() != cpu.memory().len() {
+            self.clear();
+            return 0;
+        }
+
+        // Pages written since the last record are put back as well.
+        let mut pages: Vec<usize> = cpu.pages_written_since(self.memory_writes).collect();
+        let mut rewound = 0;
+        while rewound < frames {
+            let Some(patch) = self.patches.pop_back() else {
+                break;
+            };
+            self.patch_bytes -= patch.size();
+            machine = apply(&machine, &patch.machine);
+            for (page, bytes) in patch.pages {
+                let range = page_range(page, self.memory.len());
+                self.memory[range].copy_from_slice(&bytes);
+                pages.push(page);
+            }
+            rewound += 1;
+        }
+
+        if rewound > 0 {
+            if cpu.restore_machine(&machine).is_err() {
+                // Snapshots come from `CPU::machine_snapshot`, so this only happens if the history is corrupt.
+                self.clear();
+                return 0;
+            }
+            pages.sort_unstable();
+            pages.dedup();
+            for page in pages {
+                cpu.write_page(page, &self.memory[page_range(page, self.memory.len())]);
+            }
+            self.memory_writes = cpu.memory_writes();
+        }
+        self.machine = Some(machine);
+        rewound
+    }
+
+    fn snapshot_bytes(&self) -> usize {
+        self.machine.as_ref().map_or(0, Vec::len) + self.memory.len()
+    }
+}
+
+fn page_range(page: usize, memory_size: usize) -> Range<usize> {
+    let start = page * MEMORY_PAGE_SIZE;
+    start..(start + MEMORY_PAGE_SIZE).min(memory_size)
+}
+
+// A patch is the target length followed by (skip, length, bytes) runs that overwrite
+// `from`; bytes past the end of `from` read as zero.
+fn diff(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut patch = Vec::new();
+    write_varint(&mut patch, to.len());
+
+    let source = |index: usize| from.get(index).copied().unwrap_or(0);
+    let mut position = 0;
+    let mut index = 0;
+    while index < to.len() {
+        if to[index] == source(index) {
+            index += 1;
+            continue;
+        }
+
+        let start = index;
+        let mut end = index;
+        while index < to.len() {
+            if to[index] != source(index) {
+                end = index + 1;
+            } else if index - end >= MIN_GAP {
+                break;
+            }
+            index += 1;
+        }
+
+        write_varint(&mut patch, start - position);
+        write_varint(&mut patch, end - start);
+        patch.extend_from_slice(&to[start..end]);
+        position = end;
+        index = end;
+    }
+    patch
+}
+
+fn apply(from: &[u8], patch: &[u8]) -> Vec<u8> {
+    let mut patch = patch;
+    let length = read_varint(&mut patch);
+    let mut state = from.to_vec();
+    state.resize(length, 0);
+
+    let mut position = 0;
+    while !patch.is_empty() {
+        position += read_varint(&mut patch);
+        let count = read_varint(&mut patch);
+        state[position..position + count].copy_from_slice(&patch[..count]);
+        patch = &patch[count..];
+        position += count;
+    }
+    state
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = bytes.split_first() {
+        *bytes = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+
+    // I := 0x300; loop: V0 += 1; save V0; jump loop
+    const ROM: [u8; 8] = [0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x02];
+
+    fn run(platform: Platform) -> (CPU, Rewind, Vec<Vec<u8>>) {
+        let mut cpu = CPU::with_seed(platform, 1);
+        cpu.load_rom(&ROM).unwrap();
+        let mut rewind = Rewind::new(100, usize::MAX);
+        let mut history = Vec::new();
+        for _ in 0..20 {
+            cpu.run_frame(7).unwrap();
+            rewind.record(&cpu);
+            history.push(cpu.snapshot());
+        }
+        (cpu, rewind, history)
+    }
+
+    #[test]
+    fn rewind_restores_exact_state() {
+        for platform in [Platform::Chip8, Platform::MegaChip] {
+            let (mut cpu, mut rewind, history) = run(platform);
+            assert_eq!(rewind.len(), 19);
+            assert_eq!(rewind.rewind(&mut cpu, 5), 5);
+            assert_eq!(cpu.snapshot(), history[14]);
+            assert_eq!(rewind.rewind(&mut cpu, 100), 14);
+            assert_eq!(cpu.snapshot(), history[0]);
+            assert_eq!(rewind.rewind(&mut cpu, 1), 0);
+        }
+    }
+
+    #[test]
+    fn rewind_undoes_writes_since_last_record() {
+        let (mut cpu, mut rewind, history) = run(Platform::XoChip);
+        cpu.set_memory(0x8000, 0xAA);
+        cpu.run_frame(7).unwrap();
+        assert_eq!(rewind.rewind(&mut cpu, 1), 1);
+        assert_eq!(cpu.snapshot(), history[18]);
+    }
+
+    #[test]
+    fn recording_continues_after_rewind() {
+        let (mut cpu, mut rewind, history) = run(Platform::Chip8);
+        rewind.rewind(&mut cpu, 10);
+        for _ in 0..10 {
+            cpu.run_frame(7).unwrap();
+            rewind.record(&cpu);
+        }
+        assert_eq!(cpu.snapshot(), history[19]);
+        assert_eq!(rewind.rewind(&mut cpu, 3), 3);
+        assert_eq!(cpu.snapshot(), history[16]);
+    }
+
+    #[test]
+    fn memory_budget_drops_oldest_frames() {
+        let mut cpu = CPU::with_seed(Platform::Chip8, 1);
+        cpu.load_rom(&ROM).unwrap();
+        let mut rewind = Rewind::new(100, cpu.memory().len() + cpu.machine_snapshot().len() + 1000);
+        for _ in 0..100 {
+            cpu.run_frame(7).unwrap();
+            rewind.record(&cpu);
+        }
+        assert!(!rewind.is_empty());
+        assert!(rewind.patch_bytes + rewind.snapshot_bytes() <= rewind.memory_budget);
+        assert!(rewind.len() < 99);
+    }
+}
@@ -0,0 +1,9 @@
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+// 64-bit FNV-1a, used to recognise a ROM across renames.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
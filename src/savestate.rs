@@ -0,0 +1,146 @@
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+
+// Header: magic, format version and the hash of the ROM the state was taken from, all
+// uncompressed so a state can be identified without inflating it. The machine state
+// follows as a zlib stream.
+const MAGIC: &[u8; 4] = b"C8ST";
+pub const SAVE_STATE_VERSION: u16 = 2;
+const HEADER_SIZE: usize = MAGIC.len() + 2 + 8;
+// Comfortably above the largest real state (16 MiB of MegaChip memory plus a sample buffer
+// of the same size), so a small corrupt file can't inflate without bound.
+const MAX_STATE_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub rom_hash: u64,
+}
+
+pub fn read_header(data: &[u8]) -> io::Result<Header> {
+    if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a save state"));
+    }
+    let mut reader = StateReader::new(&data[MAGIC.len()..HEADER_SIZE]);
+    let header = Header { version: reader.u16()?, rom_hash: reader.u64()? };
+    if header.version == 0 || header.version > SAVE_STATE_VERSION {
+        return Err(invalid(&format!("unsupported save state version {}", header.version)));
+    }
+    Ok(header)
+}
+
+pub(crate) fn encode(rom_hash: u64, state: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + state.len() / 4);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+    data.extend_from_slice(&rom_hash.to_le_bytes());
+
+    let mut encoder = ZlibEncoder::new(data, Compression::default());
+    // Writing into a Vec cannot fail.
+    let _ = encoder.write_all(state);
+    encoder.finish().unwrap_or_default()
+}
+
+pub(crate) fn decode(data: &[u8]) -> io::Result<(Header, Vec<u8>)> {
+    let header = read_header(data)?;
+    let mut state = Vec::new();
+    ZlibDecoder::new(&data[HEADER_SIZE..]).take(MAX_STATE_SIZE + 1).read_to_end(&mut state)?;
+    if state.len() as u64 > MAX_STATE_SIZE {
+        return Err(invalid("save state is too large"));
+    }
+    Ok((header, state))
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Little-endian field encoding for the uncompressed machine state.
+#[derive(Default)]
+pub(crate) struct StateWriter {
+    pub(crate) data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Variable-length data is prefixed with its length.
+    pub(crate) fn blob(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    pub(crate) fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated save state"));
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub(crate) fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn blob(&mut self) -> io::Result<&'a [u8]> {
+        let length = self.u32()? as usize;
+        self.bytes(length)
+    }
+}
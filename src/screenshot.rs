@@ -0,0 +1,53 @@
+use crate::palette::Palette;
+use chip8_emulator::CPU;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+// The current display as RGBA pixels, at the CPU's native resolution.
+pub fn render(cpu: &CPU, palette: &Palette) -> Vec<u8> {
+    match cpu.mega_frame() {
+        Some(mega_frame) => mega_frame.iter().flatten().copied().collect(),
+        None => cpu.display().iter().flat_map(|&planes| palette.color(planes)).collect(),
+    }
+}
+
+// `<rom>-<frame>.png`, with the frame zero-padded so captures sort in order.
+pub fn file_name(rom: &str, frame: u64) -> String {
+    let name = Path::new(rom).file_stem().map_or_else(|| rom.into(), |stem| stem.to_string_lossy());
+    format!("{}-{:06}.png", name, frame)
+}
+
+// A directory target gets a generated file name; otherwise `{rom}` and `{frame}` in the
+// path are replaced.
+pub fn resolve_path(target: &str, rom: &str, frame: u64) -> PathBuf {
+    let path = Path::new(target);
+    if path.is_dir() || target.ends_with(std::path::MAIN_SEPARATOR) || target.ends_with('/') {
+        return path.join(file_name(rom, frame));
+    }
+    let name = Path::new(rom).file_stem().map_or_else(|| rom.into(), |stem| stem.to_string_lossy());
+    PathBuf::from(target.replace("{rom}", &name).replace("{frame}", &format!("{:06}", frame)))
+}
+
+pub fn save(path: &Path, cpu: &CPU, palette: &Palette, scale: u32) -> Result<(), io::Error> {
+    let scale = scale.max(1) as usize;
+    let (width, height) = (cpu.display_width(), cpu.display_height());
+    let pixels = render(cpu, palette);
+
+    let mut image = Vec::with_capacity(pixels.len() * scale * scale);
+    for row in pixels.chunks_exact(width * 4) {
+        let scaled: Vec<u8> = row.chunks_exact(4).flat_map(|pixel| pixel.repeat(scale)).collect();
+        for _ in 0..scale {
+            image.extend_from_slice(&scaled);
+        }
+    }
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&image).map_err(io::Error::other)
+}
@@ -0,0 +1,165 @@
+use crate::cli::EmulatorOptions;
+use chip8_emulator::audio::{AudioState, Beeper, SAMPLE_RATE};
+use chip8_emulator::constants::TIMER_FREQUENCY;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / TIMER_FREQUENCY) as usize;
+const WAV_HEADER_SIZE: u32 = 44;
+
+pub trait AudioSink {
+    fn frame(&mut self, state: AudioState);
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn open_sink(options: &EmulatorOptions, headless: bool) -> Result<Box<dyn AudioSink>, String> {
+    if let Some(path) = &options.audio_out {
+        let volume = if options.mute { 0.0 } else { options.volume };
+        let beeper = Beeper::new(options.frequency, volume, SAMPLE_RATE);
+        let sink = WavSink::create(path, beeper).map_err(|e| format!("{}: {}", path, e))?;
+        return Ok(Box::new(sink));
+    }
+    if options.mute || headless {
+        return Ok(Box::new(NullSink));
+    }
+
+    #[cfg(feature = "audio")]
+    {
+        Ok(Box::new(CpalSink::open(options.frequency, options.volume)?))
+    }
+    #[cfg(not(feature = "audio"))]
+    {
+        Ok(Box::new(NullSink))
+    }
+}
+
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn frame(&mut self, _state: AudioState) {}
+}
+
+pub struct WavSink {
+    writer: BufWriter<File>,
+    beeper: Beeper,
+    buffer: [f32; SAMPLES_PER_FRAME],
+    samples_written: u32,
+    error: Option<io::Error>,
+}
+
+impl WavSink {
+    pub fn create(path: &str, beeper: Beeper) -> io::Result<WavSink> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        Self::write_header(&mut writer, 0)?;
+
+        Ok(WavSink {
+            writer,
+            beeper,
+            buffer: [0.0; SAMPLES_PER_FRAME],
+            samples_written: 0,
+            error: None,
+        })
+    }
+
+    fn write_header(writer: &mut impl Write, samples: u32) -> io::Result<()> {
+        let data_size = samples * 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&data_size.to_le_bytes())
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        self.beeper.fill(&mut self.buffer);
+        for sample in self.buffer {
+            let pcm = (sample * i16::MAX as f32) as i16;
+            self.writer.write_all(&pcm.to_le_bytes())?;
+        }
+        self.samples_written += SAMPLES_PER_FRAME as u32;
+        Ok(())
+    }
+}
+
+impl AudioSink for WavSink {
+    fn frame(&mut self, state: AudioState) {
+        self.beeper.set_state(state);
+        if self.error.is_none() {
+            self.error = self.write_frame().err();
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        Self::write_header(&mut self.writer, self.samples_written)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "audio")]
+pub struct CpalSink {
+    _stream: cpal::Stream,
+    state: std::sync::Arc<std::sync::Mutex<AudioState>>,
+}
+
+#[cfg(feature = "audio")]
+impl CpalSink {
+    pub fn open(frequency: f32, volume: f32) -> Result<CpalSink, String> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+        use std::sync::{Arc, Mutex};
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("No audio output device available")?;
+        let config: cpal::StreamConfig = device
+            .default_output_config()
+            .map_err(|e| e.to_string())?
+            .into();
+        let channels = config.channels as usize;
+        let mut beeper = Beeper::new(frequency, volume, config.sample_rate.0);
+
+        let state = Arc::new(Mutex::new(AudioState::default()));
+        let stream_state = Arc::clone(&state);
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    if let Ok(state) = stream_state.lock() {
+                        beeper.set_state(state.clone());
+                    }
+                    for frame in data.chunks_mut(channels) {
+                        let sample = beeper.next_sample();
+                        frame.fill(sample);
+                    }
+                },
+                |e| eprintln!("Audio stream error: {}", e),
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+
+        Ok(CpalSink { _stream: stream, state })
+    }
+}
+
+#[cfg(feature = "audio")]
+impl AudioSink for CpalSink {
+    fn frame(&mut self, state: AudioState) {
+        if let Ok(mut shared) = self.state.lock() {
+            *shared = state;
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::palette::Palette;
+use crate::screenshot;
+use chip8_emulator::constants::*;
+use chip8_emulator::{Platform, CPU};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+// GIF delays are in hundredths of a second, and most viewers slow anything shorter than
+// this down to 0.1s.
+const MIN_GIF_DELAY: u64 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoFormat {
+    Gif,
+    Y4m,
+    Rgb,
+}
+
+impl VideoFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Y4m => "y4m",
+            VideoFormat::Rgb => "rgb",
+        }
+    }
+
+    // `-` is stdout, which gets Y4M since the stream then describes itself.
+    fn for_path(path: &str) -> Option<VideoFormat> {
+        if path == "-" {
+            return Some(VideoFormat::Y4m);
+        }
+        let extension = Path::new(path).extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => Some(VideoFormat::Gif),
+            "y4m" => Some(VideoFormat::Y4m),
+            "rgb" | "raw" => Some(VideoFormat::Rgb),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gif" => Ok(VideoFormat::Gif),
+            "y4m" => Ok(VideoFormat::Y4m),
+            "rgb" | "raw" => Ok(VideoFormat::Rgb),
+            _ => Err(format!("unknown video format: {}", s)),
+        }
+    }
+}
+
+// `<rom>-<frame>.<ext>`, named like screenshots.
+pub fn file_name(rom: &str, frame: u64, format: VideoFormat) -> String {
+    let name = Path::new(rom).file_stem().map_or_else(|| rom.into(), |stem| stem.to_string_lossy());
+    format!("{}-{:06}.{}", name, frame, format.extension())
+}
+
+enum Output {
+    // The last distinct frame is held back until a different one arrives, so a still screen
+    // becomes one long GIF frame.
+    Gif { encoder: gif::Encoder<Box<dyn Write>>, pending: Option<Vec<u8>>, pending_since: u64 },
+    Y4m(Box<dyn Write>),
+    Rgb(Box<dyn Write>),
+}
+
+// Writes one image per emulated frame. The canvas is the platform's largest resolution, and
+// lower resolutions are stretched to fill it like the window does, so the size never changes
+// mid-stream.
+pub struct VideoRecorder {
+    output: Output,
+    canvas: (usize, usize),
+    scale: usize,
+    frames: u64,
+}
+
+impl VideoRecorder {
+    pub fn create(path: &str, platform: Platform, scale: u32) -> Result<VideoRecorder, io::Error> {
+        let format = VideoFormat::for_path(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Unknown video format, use .gif, .y4m or .rgb")
+        })?;
+        let canvas = match platform {
+            Platform::Chip8 => (CHIP8_WIDTH as usize, CHIP8_HEIGHT as usize),
+            Platform::SuperChip | Platform::XoChip => (SCHIP_WIDTH as usize, SCHIP_HEIGHT as usize),
+            Platform::MegaChip => (MEGA_WIDTH as usize, MEGA_HEIGHT as usize),
+        };
+        let scale = scale.max(1) as usize;
+        let (width, height) = (canvas.0 * scale, canvas.1 * scale);
+
+        let writer: Box<dyn Write> = if path == "-" {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+
+        let output = match format {
+            VideoFormat::Gif => {
+                if width > u16::MAX as usize || height > u16::MAX as usize {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "Video scale is too large for a GIF"));
+                }
+                let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &[]).map_err(io::Error::other)?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+                Output::Gif { encoder, pending: None, pending_since: 0 }
+            }
+            VideoFormat::Y4m => {
+                let mut writer = writer;
+                // 4:4:4 so single pixels keep their colour instead of bleeding into neighbours.
+                writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, TIMER_FREQUENCY)?;
+                Output::Y4m(writer)
+            }
+            VideoFormat::Rgb => Output::Rgb(writer),
+        };
+        Ok(VideoRecorder { output, canvas, scale, frames: 0 })
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.canvas.0 * self.scale, self.canvas.1 * self.scale)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Adds the current display as the next frame.
+    pub fn frame(&mut self, cpu: &CPU, palette: &Palette) -> Result<(), io::Error> {
+        let image = self.render(cpu, palette);
+        let frame = self.frames;
+        self.frames += 1;
+
+        let (width, height) = self.size();
+        match &mut self.output {
+            Output::Gif { encoder, pending, pending_since } => {
+                if pending.as_ref() == Some(&image) {
+                    return Ok(());
+                }
+                if pending.is_some() && gif_delay(*pending_since, frame) < MIN_GIF_DELAY {
+                    // Too short to show on its own; the new image replaces it.
+                    *pending = Some(image);
+                    return Ok(());
+                }
+                if let Some(previous) = pending.replace(image) {
+                    write_gif_frame(encoder, &previous, width, height, gif_delay(*pending_since, frame))?;
+                }
+                *pending_since = frame;
+                Ok(())
+            }
+            Output::Y4m(writer) => {
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&rgb_to_yuv444(&image))
+            }
+            Output::Rgb(writer) => writer.write_all(&image),
+        }
+    }
+
+    pub fn finish(self) -> Result<(), io::Error> {
+        let (width, height) = self.size();
+        let mut writer = match self.output {
+            Output::Gif { mut encoder, pending, pending_since } => {
+                if let Some(previous) = pending {
+                    let delay = gif_delay(pending_since, self.frames).max(MIN_GIF_DELAY);
+                    write_gif_frame(&mut encoder, &previous, width, height, delay)?;
+                }
+                encoder.into_inner().map_err(io::Error::other)?
+            }
+            Output::Y4m(writer) | Output::Rgb(writer) => writer,
+        };
+        writer.flush()
+    }
+
+    // The display as RGB, stretched to the canvas and scaled.
+    fn render(&self, cpu: &CPU, palette: &Palette) -> Vec<u8> {
+        let pixels = screenshot::render(cpu, palette);
+        let (source_width, source_height) = (cpu.display_width(), cpu.display_height());
+        let (width, height) = self.size();
+
+        let mut image = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            let source_y = y / self.scale * source_height / self.canvas.1;
+            for x in 0..width {
+                let source_x = x / self.scale * source_width / self.canvas.0;
+                let index = (source_y * source_width + source_x) * 4;
+                image.extend_from_slice(&pixels[index..index + 3]);
+            }
+        }
+        image
+    }
+}
+
+// Delay in hundredths of a second between two frames, rounded on the absolute timeline so
+// 60 Hz frames don't drift.
+fn gif_delay(from: u64, to: u64) -> u64 {
+    let hundredths = |frame: u64| frame * 100 / TIMER_FREQUENCY as u64;
+    hundredths(to) - hundredths(from)
+}
+
+fn write_gif_frame(encoder: &mut gif::Encoder<Box<dyn Write>>, image: &[u8], width: usize, height: usize, delay: u64) -> Result<(), io::Error> {
+    let (palette, indices) = quantize(image);
+    let frame = gif::Frame {
+        width: width as u16,
+        height: height as u16,
+        delay: delay.min(u16::MAX as u64) as u16,
+        palette: Some(palette),
+        buffer: Cow::Owned(indices),
+        ..gif::Frame::default()
+    };
+    encoder.write_frame(&frame).map_err(io::Error::other)
+}
+
+// Each frame gets its own palette of the colours it uses. Only MegaChip blending can produce
+// more than 256, and those frames fall back to a fixed 3-3-2 bit palette.
+fn quantize(image: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(image.len() / 3);
+    for pixel in image.chunks_exact(3) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        if let Some(&index) = colors.get(&color) {
+            indices.push(index);
+            continue;
+        }
+        if colors.len() == 256 {
+            return quantize_332(image);
+        }
+        let index = colors.len() as u8;
+        colors.insert(color, index);
+        palette.extend_from_slice(&color);
+        indices.push(index);
+    }
+    (palette, indices)
+}
+
+fn quantize_332(image: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let palette = (0..=255u8)
+        .flat_map(|index| {
+            let index = index as u32;
+            [(index >> 5) * 255 / 7, (index >> 2 & 7) * 255 / 7, (index & 3) * 255 / 3].map(|level| level as u8)
+        })
+        .collect();
+    let indices = image.chunks_exact(3).map(|pixel| pixel[0] & 0xE0 | (pixel[1] >> 5) << 2 | pixel[2] >> 6).collect();
+    (palette, indices)
+}
+
+// BT.601 studio-swing conversion, which is what ffmpeg assumes for untagged Y4M.
+fn rgb_to_yuv444(image: &[u8]) -> Vec<u8> {
+    let count = image.len() / 3;
+    let mut planes = vec![0; count * 3];
+    for (index, pixel) in image.chunks_exact(3).enumerate() {
+        let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+        planes[index] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        planes[count + index] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        planes[count * 2 + index] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_falls_back_to_332_palette_above_256_colours() {
+        let image: Vec<u8> = (0..300u32).flat_map(|color| [color as u8, (color >> 8) as u8, 0x80]).collect();
+        let (palette, indices) = quantize(&image);
+        assert_eq!(palette.len(), 256 * 3);
+        assert_eq!(indices.len(), 300);
+        assert_eq!(&palette[..3], &[0, 0, 0]);
+        assert_eq!(&palette[255 * 3..], &[255, 255, 255]);
+        assert_eq!(indices[0xFF], 0xE0 | 2);
+    }
+
+    #[test]
+    fn quantize_keeps_exact_colours_up_to_256() {
+        let image = [1, 2, 3, 4, 5, 6, 1, 2, 3];
+        assert_eq!(quantize(&image), (vec![1, 2, 3, 4, 5, 6], vec![0, 1, 0]));
+    }
+
+    #[test]
+    fn file_name_uses_rom_stem() {
+        assert_eq!(file_name("roms/a.png.ch8", 42, VideoFormat::Y4m), "a.png-000042.y4m");
+    }
+}